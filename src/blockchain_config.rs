@@ -13,12 +13,12 @@
 */
 
 use ton_block::{
-    ConfigParam18, ConfigParams, FundamentalSmcAddresses, 
+    ConfigParam18, ConfigParamEnum, ConfigParams, FundamentalSmcAddresses, GlobalCapabilities,
     GasFlatPfx, GasLimitsPrices, GasPrices, GasPricesEx, Message, MsgAddressInt, 
     MsgForwardPrices, Serializable, StorageInfo, StoragePrices, StorageUsedShort,
-    MASTERCHAIN_ID
+    BASE_WORKCHAIN_ID, MASTERCHAIN_ID
 };
-use ton_types::{AccountId, BuilderData, Result};
+use ton_types::{AccountId, BuilderData, fail, Result};
 
 pub trait TONDefaultConfig {
     /// Get default value for masterchain
@@ -123,7 +123,7 @@ impl Default for AccStoragePrices {
 
 impl AccStoragePrices {
     /// Calculate storage fee for provided data
-    pub fn calc_storage_fee(&self, cells: u128, bits: u128, mut last_paid: u32, now: u32, is_masterchain: bool) -> u128 {
+    pub fn calc_storage_fee(&self, cells: u128, bits: u128, mut last_paid: u32, now: u32, is_masterchain: bool) -> Result<u128> {
         let mut fee = 0u128;
         // storage prices config contains prices array for some time intervals
         // to calculate account storage fee we need to sum fees for all intervals since last
@@ -137,7 +137,12 @@ impl AccStoragePrices {
             };
 
             if end >= last_paid {
-                let delta = end - std::cmp::max(prices.utime_since, last_paid);
+                let delta = match end.checked_sub(std::cmp::max(prices.utime_since, last_paid)) {
+                    Some(delta) => delta,
+                    None => fail!(
+                        "storage prices interval starting at {} ends before it at {}", prices.utime_since, end
+                    )
+                };
                 fee += if is_masterchain {
                     (cells * prices.mc_cell_price_ps as u128 + bits * prices.mc_bit_price_ps as u128) * delta as u128
                 } else {
@@ -149,7 +154,7 @@ impl AccStoragePrices {
 
         // stirage fee is calculated in pseudo values (like forward fee and gas fee) - multiplied
         // to 0xffff, so divide by this value with ceil rounding
-        (fee + 0xffff) >> 16
+        Ok((fee + 0xffff) >> 16)
     }
 
     fn with_config(config: &ConfigParam18) -> Result<Self> {
@@ -162,6 +167,39 @@ impl AccStoragePrices {
     }
 }
 
+/// Size limits of outbound message
+#[derive(Debug, Clone)]
+pub struct MsgSizeLimits {
+    pub max_msg_cells: u64,
+    pub max_msg_bits: u64,
+    pub max_msg_depth: u16,
+}
+
+impl Default for MsgSizeLimits {
+    fn default() -> Self {
+        MsgSizeLimits {
+            max_msg_cells: 1 << 13,
+            max_msg_bits: 1 << 21,
+            max_msg_depth: 512,
+        }
+    }
+}
+
+impl MsgSizeLimits {
+    /// Get limits from size limits config param, default values are used if it is absent.
+    /// Config has no message depth limit (`max_vm_data_depth` limits VM data), so it is default
+    fn with_config(config: &ConfigParams) -> Self {
+        match config.config(43) {
+            Ok(Some(ConfigParamEnum::ConfigParam43(limits))) => MsgSizeLimits {
+                max_msg_cells: limits.max_msg_cells as u64,
+                max_msg_bits: limits.max_msg_bits as u64,
+                ..MsgSizeLimits::default()
+            },
+            _ => MsgSizeLimits::default()
+        }
+    }
+}
+
 /// Gas parameters
 #[derive(Debug, Clone)]
 pub struct GasConfigFull {
@@ -291,8 +329,12 @@ pub struct BlockchainConfig {
     
     storage_prices: AccStoragePrices,
 
+    msg_size_limits: MsgSizeLimits,
+
     special_contracts: FundamentalSmcAddresses,
 
+    capabilities: u64,
+
     raw_config: ConfigParams,
 }
 
@@ -304,7 +346,9 @@ impl Default for BlockchainConfig {
             fwd_prices_mc: MsgForwardPrices::default_mc(),
             fwd_prices_wc: MsgForwardPrices::default_wc(),
             storage_prices: AccStoragePrices::default(),
+            msg_size_limits: MsgSizeLimits::default(),
             special_contracts: Self::get_default_special_contracts(),
+            capabilities: 0,
             raw_config: Self::get_defult_raw_config(),
         }
     }
@@ -354,8 +398,12 @@ impl BlockchainConfig {
             
             storage_prices: AccStoragePrices::with_config(&config.storage_prices()?)?,
 
+            msg_size_limits: MsgSizeLimits::with_config(&config),
+
             special_contracts: config.fundamental_smc_addr()?,
 
+            capabilities: config.capabilities(),
+
             raw_config: config,
         })
     }
@@ -371,6 +419,16 @@ impl BlockchainConfig {
         }
     }
 
+    /// Get raw config params (used by contracts via CONFIGPARAM instructions)
+    pub fn raw_config(&self) -> &ConfigParams {
+        &self.raw_config
+    }
+
+    /// Get size limits of outbound message
+    pub fn get_msg_size_limits(&self) -> &MsgSizeLimits {
+        &self.msg_size_limits
+    }
+
     /// Calculate gas fee for account
     pub fn calc_gas_fee(&self, gas_used: u64, address: &MsgAddressInt) -> u128 {
         self.get_gas_config(address).calc_gas_fee(gas_used)
@@ -386,7 +444,7 @@ impl BlockchainConfig {
     }
 
     /// Calculate account storage fee
-    pub fn calc_storage_fee(&self, storage: &StorageInfo, address: &MsgAddressInt, now: u32) -> u128 {        
+    pub fn calc_storage_fee(&self, storage: &StorageInfo, address: &MsgAddressInt, now: u32) -> Result<u128> {        
         self.storage_prices.calc_storage_fee(
             u128::from(storage.used.cells.0),
             u128::from(storage.used.bits.0),
@@ -411,8 +469,34 @@ impl BlockchainConfig {
         }
     }
 
+    /// Check if messages can be sent to workchain.
+    /// Masterchain is always accessible, other workchains must be described in config
+    /// as active and accepting messages. If config has no workchains description
+    /// (or it is empty) only base workchain is accessible.
+    pub fn is_workchain_accessible(&self, workchain_id: i32) -> bool {
+        if workchain_id == MASTERCHAIN_ID {
+            return true
+        }
+        match self.raw_config.workchains() {
+            Ok(ref workchains) if !workchains.is_empty() => match workchains.get(&workchain_id) {
+                Ok(Some(descr)) => descr.active && descr.accept_msgs,
+                _ => false
+            },
+            _ => workchain_id == BASE_WORKCHAIN_ID
+        }
+    }
+
+    /// Check if global capability is enabled in config
+    pub fn has_capability(&self, capability: GlobalCapabilities) -> bool {
+        (self.capabilities & (capability as u64)) != 0
+    }
+
     /// Check if address belongs to masterchain
     pub fn is_masterchain_address(address: &MsgAddressInt) -> bool {
         address.get_workchain_id() == MASTERCHAIN_ID
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+#[path = "tests/test_blockchain_config.rs"]
+mod tests;
\ No newline at end of file
@@ -12,8 +12,58 @@
 * limitations under the License.
 */
 
+use std::fmt;
+use ton_block::MsgAddressInt;
 use ton_types::types::ExceptionCode;
 
+/// Phase of transaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrPhase {
+    Storage,
+    Credit,
+    Compute,
+    Action,
+    Bounce,
+}
+
+impl fmt::Display for TrPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TrPhase::Storage => "storage",
+            TrPhase::Credit => "credit",
+            TrPhase::Compute => "compute",
+            TrPhase::Action => "action",
+            TrPhase::Bounce => "bounce",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Reason of inbound external message rejection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExtMsgRejectReason {
+    /// Account does not exist or is not initialized and message has no suitable StateInit
+    NoState,
+    /// Account is frozen
+    Frozen,
+    /// Account cannot pay import fee of message
+    CannotPayImportFee,
+    /// Contract did not accept message (did not execute ACCEPT before gas credit was spent)
+    NotAccepted,
+}
+
+impl fmt::Display for ExtMsgRejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let reason = match self {
+            ExtMsgRejectReason::NoState => "account has no state",
+            ExtMsgRejectReason::Frozen => "account is frozen",
+            ExtMsgRejectReason::CannotPayImportFee => "cannot pay import fee",
+            ExtMsgRejectReason::NotAccepted => "contract did not accept message",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
 #[derive(Debug, failure::Fail)]
 pub enum ExecutorError {   
     #[fail(display = "Invalid external message")]
@@ -21,5 +71,64 @@ pub enum ExecutorError {
     #[fail(display = "Transaction executor internal error: {}", 0)]
     TrExecutorError(String),
     #[fail(display = "VM Exception, code: {}", 0)]
-    TvmExceptionCode(ExceptionCode)
+    TvmExceptionCode(ExceptionCode),
+    #[fail(display = "{} phase failed for account {}: {}", phase, address, cause)]
+    PhaseFailed {
+        phase: TrPhase,
+        address: String,
+        cause: String,
+    },
+    #[fail(display = "Invalid account {}: {}", address, cause)]
+    InvalidAccount {
+        address: String,
+        cause: String,
+    },
+    #[fail(display = "Invalid inbound message: {}", 0)]
+    InvalidMessage(String),
+    #[fail(display = "Block gas limit is reached, message must be processed in the next block")]
+    BlockGasLimitReached,
+    #[fail(
+        display = "External message is rejected: {}, exit code: {:?}, gas used: {:?}",
+        reason, exit_code, gas_used
+    )]
+    ExtMessageRejected {
+        reason: ExtMsgRejectReason,
+        /// VM exit code and gas used are `None` if message was rejected before VM run
+        exit_code: Option<i32>,
+        gas_used: Option<u64>,
+        exception: Option<String>,
+    },
+}
+
+impl ExecutorError {
+    /// Create error of transaction phase, account address can be unknown
+    pub fn phase_failed(phase: TrPhase, address: Option<&MsgAddressInt>, cause: impl ToString) -> Self {
+        ExecutorError::PhaseFailed {
+            phase,
+            address: address_to_string(address),
+            cause: cause.to_string(),
+        }
+    }
+
+    /// Create rejection error of external message which was not passed to VM
+    pub fn ext_message_rejected(reason: ExtMsgRejectReason) -> Self {
+        ExecutorError::ExtMessageRejected {
+            reason,
+            exit_code: None,
+            gas_used: None,
+            exception: None,
+        }
+    }
+
+    /// Create error of malformed account, account address can be unknown
+    pub fn invalid_account(address: Option<&MsgAddressInt>, cause: impl ToString) -> Self {
+        ExecutorError::InvalidAccount {
+            address: address_to_string(address),
+            cause: cause.to_string(),
+        }
+    }
+}
+
+fn address_to_string(address: Option<&MsgAddressInt>) -> String {
+    address.map(|address| address.to_string()).unwrap_or_else(|| "<none>".to_string())
 }
@@ -18,10 +18,19 @@ pub mod transaction_executor;
 pub use transaction_executor::*;
 
 pub mod ordinary_transaction;
-pub use ordinary_transaction::OrdinaryTransactionExecutor;
+pub use ordinary_transaction::{OrdinaryStackBuilder, OrdinaryTransactionExecutor};
 
 pub mod tick_tock_transaction;
-pub use tick_tock_transaction::TickTockTransactionExecutor;
+pub use tick_tock_transaction::{TickTockStackBuilder, TickTockTransactionExecutor};
+
+pub mod storage_transaction;
+pub use storage_transaction::StorageTransactionExecutor;
+
+pub mod split_merge_transaction;
+pub use split_merge_transaction::{
+    MergeInstallTransactionExecutor, MergePrepareTransactionExecutor,
+    SplitInstallTransactionExecutor, SplitPrepareTransactionExecutor,
+};
 
 pub mod tr_phases;
 pub use tr_phases::*;
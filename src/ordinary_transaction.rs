@@ -14,19 +14,24 @@
 
 
 use crate::{
-    blockchain_config::{BlockchainConfig, CalcMsgFwdFees}, error::ExecutorError,
-    TransactionExecutor,
-    tr_phases::{compute_phase, bounce_phase, credit_phase, storage_phase, action_phase},
+    blockchain_config::{BlockchainConfig, CalcMsgFwdFees}, error::{ExecutorError, ExtMsgRejectReason},
+    ExecuteParams, StackBuilder, TransactionExecutor, TransactionLt,
+    tr_phases::{
+        compute_phase, bounce_phase, charge_gas_fees, credit_phase, storage_phase, action_phase,
+        ComputePhaseParams,
+    },
 };
 
-use std::{sync::{atomic::{AtomicU64, Ordering}, Arc}};
+#[cfg(feature="timings")]
+use std::sync::atomic::{AtomicU64, Ordering};
 #[cfg(feature="timings")]
 use std::time::Instant;
 use ton_block::{
-    AddSub, CurrencyCollection,
+    AccStatusChange, AddSub, ComputeSkipReason, CurrencyCollection,
     accounts::{Account},
     messages::{CommonMsgInfo, Message},
-    HashUpdate, Serializable, Deserializable, Transaction, TrComputePhase, TransactionDescrOrdinary, TransactionDescr,
+    HashUpdate, Serializable, Deserializable, Transaction, TrComputePhase, TrComputePhaseSkipped,
+    TransactionDescrOrdinary, TransactionDescr,
 };
 use ton_types::{Cell, error, fail, Result};
 use ton_vm::{
@@ -34,8 +39,45 @@ use ton_vm::{
 };
 
 
+/// Builds standard initial stack of ordinary transaction:
+/// account balance, message value, message cell, message body and function selector
+/// (0 for internal message and -1 for external one).
+#[derive(Clone, Debug, Default)]
+pub struct OrdinaryStackBuilder;
+
+impl StackBuilder for OrdinaryStackBuilder {
+    fn build_stack(&self, in_msg: Option<&Message>, account: &Account) -> Result<Stack> {
+        let in_msg = in_msg.ok_or_else(|| ExecutorError::InvalidMessage(
+            "ordinary transaction must have input message".to_string()))?;
+        let account_balance = account.get_balance()
+            .ok_or_else(|| ExecutorError::invalid_account(account.get_addr(), "account has no balance"))?;
+        let account_balance = int!(account_balance.grams.0.clone());
+        let msg_balance = int!(
+            in_msg.get_value().map(|val| val.grams.value().clone()).unwrap_or_default()
+        );
+        let function_selector = match in_msg.header() {
+            CommonMsgInfo::IntMsgInfo(_) => int!(0),
+            _ => int!(-1),
+        };
+
+        let body_slice = in_msg.body().unwrap_or_default();
+
+        let msg_cell = Cell::from(in_msg.write_to_new_cell()?);
+        let mut stack = Stack::new();
+        stack
+            .push(account_balance)
+            .push(msg_balance)
+            .push(StackItem::Cell(msg_cell))
+            .push(StackItem::Slice(body_slice))
+            .push(function_selector);
+        
+        Ok(stack)
+    }
+}
+
 pub struct OrdinaryTransactionExecutor {
     config: BlockchainConfig,
+    stack_builder: Box<dyn StackBuilder>,
 
     #[cfg(feature="timings")]
     timings: [AtomicU64; 3],
@@ -43,8 +85,14 @@ pub struct OrdinaryTransactionExecutor {
 
 impl OrdinaryTransactionExecutor {
     pub fn new(config: BlockchainConfig) -> Self {
+        Self::with_stack_builder(config, Box::new(OrdinaryStackBuilder))
+    }
+
+    /// Create executor which uses custom builder of VM stack and contract info
+    pub fn with_stack_builder(config: BlockchainConfig, stack_builder: Box<dyn StackBuilder>) -> Self {
         Self {
             config,
+            stack_builder,
             
             #[cfg(feature="timings")]
             timings: [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)],
@@ -60,15 +108,12 @@ impl OrdinaryTransactionExecutor {
 impl TransactionExecutor for OrdinaryTransactionExecutor {
     ///
     /// Create end execute transaction from message for account
-    fn execute(
+    fn execute_with_params(
         &self,
         in_msg: Option<&Message>,
         account_root: &mut Cell,
-        block_unixtime: u32,
-        block_lt: u64,
-        last_tr_lt: Arc<AtomicU64>,
-        debug: bool
-    ) -> Result<Transaction> {
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
         #[cfg(feature="timings")]
         let mut now = Instant::now();
 
@@ -87,8 +132,8 @@ impl TransactionExecutor for OrdinaryTransactionExecutor {
 
         let old_hash = account_root.repr_hash();
         let mut account = Account::construct_from(&mut account_root.clone().into())?;
-        let account_address = &in_msg.dst().ok_or(ExecutorError::TrExecutorError(
-            "Input message has no dst address".to_string()))?;
+        let account_address = &in_msg.dst().ok_or(ExecutorError::InvalidMessage(
+            "input message has no dst address".to_string()))?;
         match account.get_id() {
             Some(account_id) => log::debug!(target: "executor", "Account = {:x}", account_id),
             None => log::debug!(target: "executor",
@@ -98,34 +143,44 @@ impl TransactionExecutor for OrdinaryTransactionExecutor {
         // TODO: maybe fail if special or check tick tock only
         let is_special = self.config.is_special_account(account_address)?;
 
-        let lt = last_tr_lt.fetch_add(1, Ordering::SeqCst);
+        let lt_reservation = TransactionLt::reserve(
+            &params.last_tr_lt,
+            account.last_tr_time().unwrap_or_default(),
+            Some(in_msg)
+        );
+        let lt = lt_reservation.start();
+        // lt for the next outbound message
+        let mut next_lt = lt + 1;
         let mut tr = Transaction::with_account_and_message(&account, &in_msg, lt)?;
-        tr.set_now(block_unixtime);
+        tr.prev_trans_hash = params.prev_trans_hash;
+        tr.prev_trans_lt = params.prev_trans_lt;
+        tr.set_now(params.block_unixtime);
         let mut description = TransactionDescrOrdinary::default();
         description.credit_first = credit_first;
 
         // TODO: add and process ihr_delivered parameter (if ihr_delivered ihr_fee is added to total fees)
-        // TODO: add msg_balance_remaining variable and use it in phases 
 
         if is_ext_msg && !is_special {
             let (_, in_fwd_fee) = self.config.get_fwd_prices(&in_msg).calc_fwd_fee(&in_msg)?;
             let in_fwd_fee = CurrencyCollection::with_grams(in_fwd_fee as u64);
             if !account.sub_funds(&in_fwd_fee)? {
-                fail!(ExecutorError::TrExecutorError(
-                    "Cannot pay for importing this external message".to_string()))
+                fail!(ExecutorError::ext_message_rejected(ExtMsgRejectReason::CannotPayImportFee))
             }
             tr.set_total_fees(in_fwd_fee);
         }
 
         if credit_first {
-            description.credit_ph = credit_phase(&in_msg, &mut account);
+            description.credit_ph = credit_phase(&in_msg, &mut account)?;
         }
-        description.storage_ph = storage_phase(&mut account, &mut tr, &self.config, is_special);
+        description.storage_ph = storage_phase(&mut account, &mut tr, &self.config, is_special)?;
         log::debug!(target: "executor",
             "storage_phase: {}", if description.storage_ph.is_some() {"present"} else {"none"});
+        description.destroyed = description.storage_ph.as_ref()
+            .map(|phase| phase.status_change == AccStatusChange::Deleted)
+            .unwrap_or(false);
 
         if !credit_first {
-            description.credit_ph = credit_phase(&in_msg, &mut account);
+            description.credit_ph = credit_phase(&in_msg, &mut account)?;
         }
         log::debug!(target: "executor", 
             "credit_phase: {}", if description.credit_ph.is_some() {"present"} else {"none"});
@@ -136,17 +191,39 @@ impl TransactionExecutor for OrdinaryTransactionExecutor {
             now = Instant::now();
         }
 
-        let smci = self.build_contract_info(&account, &account_address, block_unixtime, block_lt, lt); 
+        // remaining value of inbound message, it is decreased by gas fees in compute phase
+        let mut msg_balance = in_msg.get_value().cloned().unwrap_or_default();
+        // account state after storage and credit phases, it is restored if transaction is aborted
+        let account_before_compute = account.clone();
+
+        let smci = self.stack_builder.build_contract_info(
+            &self.config, &account, &account_address, lt, &params
+        );
         log::debug!(target: "executor", "compute_phase");
-        let (compute_ph, actions) = compute_phase(
-            Some(&in_msg), 
-            &mut account, 
-            &smci,
-            self,
-            self.config.get_gas_config(account_address),
+        let mut compute_params = ComputePhaseParams {
+            gas_config: self.config.get_gas_config(account_address),
             is_special,
-            debug
-        )?;
+            block_gas: params.block_gas.as_deref().map(|block_gas| block_gas.reservation()),
+            debug: params.debug,
+        };
+        let (compute_ph, actions) = if description.destroyed {
+            // account deleted by storage phase is not created again by the message,
+            // credited value (if any) has been spent on storage fees
+            log::debug!(target: "executor", "account is deleted by storage phase: skip computing phase");
+            if is_ext_msg {
+                fail!(ExecutorError::ext_message_rejected(ExtMsgRejectReason::NoState))
+            }
+            (TrComputePhase::Skipped(TrComputePhaseSkipped { reason: ComputeSkipReason::NoState }), None)
+        } else {
+            compute_phase(
+                Some(&in_msg), 
+                &mut account, 
+                &mut msg_balance,
+                &smci,
+                self.stack_builder.as_ref(),
+                &mut compute_params,
+            )?
+        };
         description.compute_ph = compute_ph;
         description.action = match description.compute_ph {
             TrComputePhase::Vm(ref phase) => {
@@ -154,7 +231,9 @@ impl TransactionExecutor for OrdinaryTransactionExecutor {
                 if phase.success {
                     log::debug!(target: "executor", "compute_phase: TrComputePhase::Vm success");
                     log::debug!(target: "executor", "action_phase");
-                    action_phase(&mut tr, &mut account, actions, &self.config, last_tr_lt.clone(), is_special)
+                    // bounce phase needs remaining value of inbound message if actions fail
+                    let mut action_msg_balance = msg_balance.clone();
+                    action_phase(&mut tr, &mut account, actions, &mut action_msg_balance, &self.config, &mut next_lt, is_special)?
                 } else {
                     log::debug!(target: "executor", "compute_phase: TrComputePhase::Vm failed");
                     None
@@ -183,50 +262,66 @@ impl TransactionExecutor for OrdinaryTransactionExecutor {
         };
         
         log::debug!(target: "executor", "Desciption.aborted {}", description.aborted);
-        account.set_last_tr_time(lt);
 
         if description.aborted {
+            // changes of compute and action phases are rolled back, 
+            // but account created by inbound message keeps credited value as uninitialized
+            account = match (account_before_compute, &account) {
+                (Account::AccountNone, Account::AccountNone) => Account::AccountNone,
+                (Account::AccountNone, _) => {
+                    let mut new_account = Account::with_address_and_ballance(
+                        account_address, 
+                        in_msg.get_value().unwrap_or(&CurrencyCollection::default())
+                    );
+                    new_account.set_last_paid(tr.now());
+                    new_account
+                }
+                (account_before_compute, _) => account_before_compute,
+            };
+            // gas fees must be paid in any case
+            if let TrComputePhase::Vm(ref mut phase) = description.compute_ph {
+                charge_gas_fees(&mut tr, &mut account, phase)?;
+            }
+            // outbound messages of action phase are not created
+            next_lt = lt + 1;
             log::debug!(target: "executor", "bounce_phase");
-            let fwd_prices = self.config.get_fwd_prices(&in_msg);
-            description.bounce = bounce_phase(in_msg.clone(), &mut account, &mut tr, 0, fwd_prices);
+            description.bounce = bounce_phase(
+                in_msg.clone(), &msg_balance, &mut account, &mut tr, &mut next_lt, &self.config
+            )?;
         } else {
-            tr.set_end_status(account.status());
-            *account_root = account.write_to_new_cell()?.into();
-
-            // calculate Hash update
-            log::debug!(target: "executor", "calculate Hash update");
-            let new_hash = account_root.repr_hash();
-            tr.write_state_update(&HashUpdate::with_hashes(old_hash, new_hash))?;
+            let deleted = description.action.as_ref()
+                .map(|phase| phase.status_change == AccStatusChange::Deleted)
+                .unwrap_or(false);
+            if deleted {
+                log::debug!(target: "executor", "account is deleted");
+                account = Account::AccountNone;
+                description.destroyed = true;
+            }
         }
-        tr.write_description(&TransactionDescr::Ordinary(description))?;
+        // account keeps end lt of its last transaction
+        account.set_last_tr_time(next_lt);
+        // code, data and libraries could be changed, so storage usage must be recalculated
+        account.update_storage_stat()?;
 
-        #[cfg(feature="timings")]
-        self.timings[2].fetch_add(now.elapsed().as_micros() as u64, Ordering::SeqCst);
+        tr.set_end_status(account.status());
+        *account_root = account.write_to_new_cell()?.into();
 
-        Ok(tr)
-    }
-    fn build_stack(&self, in_msg: Option<&Message>, account: &Account) -> Stack {
-        let in_msg = in_msg.unwrap();
-        let account_balance = int!(account.get_balance().unwrap().grams.0.clone());
-        let msg_balance = int!(
-            in_msg.get_value().map(|val| val.grams.value().clone()).unwrap_or_default()
-        );
-        let function_selector = match in_msg.header() {
-            CommonMsgInfo::IntMsgInfo(_) => int!(0),
-            _ => int!(-1),
+        // calculate Hash update
+        log::debug!(target: "executor", "calculate Hash update");
+        let new_hash = account_root.repr_hash();
+        tr.write_state_update(&HashUpdate::with_hashes(old_hash, new_hash))?;
+
+        let gas_used = match description.compute_ph {
+            TrComputePhase::Vm(ref phase) => phase.gas_used.0,
+            TrComputePhase::Skipped(_) => 0,
         };
+        tr.write_description(&TransactionDescr::Ordinary(description))?;
+        // transaction is executed, so gas it has used is taken from block
+        compute_params.commit_block_gas(gas_used);
 
-        let body_slice = in_msg.body().unwrap_or_default();
+        #[cfg(feature="timings")]
+        self.timings[2].fetch_add(now.elapsed().as_micros() as u64, Ordering::SeqCst);
 
-        let msg_cell = Cell::from(in_msg.write_to_new_cell().unwrap());
-        let mut stack = Stack::new();
-        stack
-            .push(account_balance)
-            .push(msg_balance)
-            .push(StackItem::Cell(msg_cell))
-            .push(StackItem::Slice(body_slice))
-            .push(function_selector);
-        
-        stack
+        Ok((tr, lt_reservation.commit(next_lt)))
     }
 }
@@ -0,0 +1,450 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+//! Executors of transactions which split and merge accounts when their shard is split or merged.
+//! Prepare transaction of account sends its value (and state for split) to sibling account
+//! by internal message, install transaction creates or credits sibling account with this message.
+//! Messages between account and its sibling are not charged with forwarding fees.
+
+use crate::{
+    blockchain_config::BlockchainConfig,
+    error::{ExecutorError, TrPhase},
+    ExecuteParams, TransactionExecutor, TransactionLt,
+    tr_phases::{credit_phase, storage_phase},
+};
+
+use num_traits::ToPrimitive;
+use ton_block::{
+    Account, AccountState, AccStatusChange, ChildCell, ComputeSkipReason, CurrencyCollection,
+    Deserializable, Grams, HashUpdate, InternalMessageHeader, Message, MsgAddressInt,
+    Serializable, SplitMergeInfo, Transaction, TrActionPhase, TrComputePhase, TrComputePhaseSkipped,
+    TransactionDescr, TransactionDescrMergeInstall, TransactionDescrMergePrepare,
+    TransactionDescrSplitInstall, TransactionDescrSplitPrepare, VarUInteger32,
+};
+use ton_types::{error, fail, AccountId, Cell, Result, UInt256};
+
+
+/// Checks that split info describes account split or merged with the shard:
+/// account must be split deeper than current shard prefix, account address
+/// and sibling address must differ only in the bit next to shard prefix.
+fn check_split_info(info: &SplitMergeInfo) -> Result<()> {
+    if info.cur_shard_pfx_len >= info.acc_split_depth {
+        fail!(ExecutorError::TrExecutorError(format!(
+            "account split depth {} must be greater than shard prefix length {}",
+            info.acc_split_depth, info.cur_shard_pfx_len
+        )))
+    }
+    let bit = info.cur_shard_pfx_len as usize;
+    let this_addr = info.this_addr.as_slice();
+    let sibling_addr = info.sibling_addr.as_slice();
+    for i in 0..this_addr.len() {
+        let diff = if i == bit / 8 { 0x80 >> (bit % 8) } else { 0 };
+        if this_addr[i] ^ sibling_addr[i] != diff {
+            fail!(ExecutorError::TrExecutorError(format!(
+                "account and sibling addresses must differ only in bit {}", bit
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Returns address of account which must be equal to `this_addr` of split info
+fn check_account_address(account: &Account, info: &SplitMergeInfo) -> Result<MsgAddressInt> {
+    let address = account.get_addr().cloned()
+        .ok_or_else(|| ExecutorError::invalid_account(None, "account has no address"))?;
+    if address.get_address() != AccountId::from(info.this_addr.clone()) {
+        fail!(ExecutorError::invalid_account(Some(&address), "account address is not equal to split info address"))
+    }
+    Ok(address)
+}
+
+/// Address of sibling account in the same workchain
+fn sibling_address(address: &MsgAddressInt, info: &SplitMergeInfo) -> Result<MsgAddressInt> {
+    let sibling_addr = AccountId::from(info.sibling_addr.clone());
+    match address {
+        MsgAddressInt::AddrStd(addr) => MsgAddressInt::with_standart(None, addr.workchain_id, sibling_addr),
+        MsgAddressInt::AddrVar(addr) => MsgAddressInt::with_variant(None, addr.workchain_id, sibling_addr),
+    }
+}
+
+/// Finds message sent by prepare transaction to account being installed
+fn prepare_message(prepare_transaction: &Transaction, info: &SplitMergeInfo) -> Result<Message> {
+    let this_addr = AccountId::from(info.this_addr.clone());
+    let mut result = None;
+    prepare_transaction.iterate_out_msgs(|msg| {
+        if msg.int_dst_account_id() == Some(this_addr.clone()) {
+            result = Some(msg);
+            return Ok(false)
+        }
+        Ok(true)
+    })?;
+    result.ok_or_else(|| error!(ExecutorError::InvalidMessage(
+        "prepare transaction has no message to installed account".to_string()
+    )))
+}
+
+/// Half of balance sent to sibling account by split,
+/// odd nanogram (or unit of extra currency) stays on account
+fn half_balance(balance: &CurrencyCollection) -> Result<CurrencyCollection> {
+    let grams = balance.grams.0.to_u128()
+        .ok_or_else(|| error!("failed to convert account balance to u128"))?;
+    let mut half = CurrencyCollection::from_grams(Grams((grams / 2).into()));
+    balance.other.iterate_with_keys(|id: u32, amount: VarUInteger32| -> Result<bool> {
+        let amount = amount.0.to_u128()
+            .ok_or_else(|| error!("failed to convert extra currency {} to u128", id))?;
+        half.set_other(id, amount / 2)?;
+        Ok(true)
+    })?;
+    Ok(half)
+}
+
+/// Internal message from account to its sibling, it is not bounceable
+fn sibling_message(src: MsgAddressInt, dst: MsgAddressInt, value: CurrencyCollection, now: u32, lt: u64) -> Message {
+    let mut header = InternalMessageHeader::with_addresses(src, dst, value);
+    header.ihr_disabled = true;
+    header.bounce = false;
+    header.created_at = now.into();
+    header.created_lt = lt;
+    Message::with_int_header(header)
+}
+
+fn new_transaction(account_id: AccountId, account: &Account, params: &ExecuteParams, lt: u64) -> Transaction {
+    let mut tr = Transaction::with_address_and_status(account_id, account.status());
+    tr.set_logical_time(lt);
+    tr.prev_trans_hash = params.prev_trans_hash.clone();
+    tr.prev_trans_lt = params.prev_trans_lt;
+    tr.set_now(params.block_unixtime);
+    tr
+}
+
+/// Writes new account state, state update and description of transaction
+fn finish_transaction(
+    tr: &mut Transaction,
+    account: &mut Account,
+    account_root: &mut Cell,
+    old_hash: UInt256,
+    end_lt: u64,
+    description: &TransactionDescr,
+) -> Result<()> {
+    // account keeps end lt of its last transaction
+    account.set_last_tr_time(end_lt);
+    account.update_storage_stat()?;
+
+    tr.set_end_status(account.status());
+    *account_root = account.write_to_new_cell()?.into();
+
+    log::debug!(target: "executor", "calculate Hash update");
+    let new_hash = account_root.repr_hash();
+    tr.write_state_update(&HashUpdate::with_hashes(old_hash, new_hash))?;
+    tr.write_description(description)
+}
+
+fn skipped_compute_phase() -> TrComputePhase {
+    TrComputePhase::Skipped(TrComputePhaseSkipped { reason: ComputeSkipReason::NoState })
+}
+
+/// Executor of split prepare transaction.
+/// After storage phase active account sends half of its balance and its state
+/// to sibling account. Transaction of inactive account is aborted.
+pub struct SplitPrepareTransactionExecutor {
+    pub config: BlockchainConfig,
+    pub split_info: SplitMergeInfo,
+}
+
+impl SplitPrepareTransactionExecutor {
+    pub fn new(config: BlockchainConfig, split_info: SplitMergeInfo) -> Self {
+        Self {
+            config,
+            split_info,
+        }
+    }
+}
+
+impl TransactionExecutor for SplitPrepareTransactionExecutor {
+    ///
+    /// Create end execute split prepare transaction for account
+    fn execute_with_params(
+        &self,
+        in_msg: Option<&Message>,
+        account_root: &mut Cell,
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
+        if in_msg.is_some() {
+            fail!("Split prepare transaction must not have input message")
+        }
+        check_split_info(&self.split_info)?;
+        let old_hash = account_root.repr_hash();
+        let mut account = Account::construct_from(&mut account_root.clone().into())?;
+        let address = check_account_address(&account, &self.split_info)?;
+        let is_special = self.config.is_special_account(&address)?;
+        let lt_reservation = TransactionLt::reserve(
+            &params.last_tr_lt,
+            account.last_tr_time().unwrap_or_default(),
+            None
+        );
+        let lt = lt_reservation.start();
+        // lt for the next outbound message
+        let mut next_lt = lt + 1;
+        let mut tr = new_transaction(address.get_address(), &account, &params, lt);
+
+        let mut description = TransactionDescrSplitPrepare::default();
+        description.split_info = self.split_info.clone();
+        description.storage_ph = storage_phase(&mut account, &mut tr, &self.config, is_special)?;
+        description.destroyed = description.storage_ph.as_ref()
+            .map(|phase| phase.status_change == AccStatusChange::Deleted)
+            .unwrap_or(false);
+        description.compute_ph = skipped_compute_phase();
+
+        let state_init = match account.state() {
+            Some(AccountState::AccountActive(state_init)) => Some(state_init.clone()),
+            _ => None
+        };
+        match state_init {
+            Some(state_init) => {
+                let balance = account.get_balance().cloned().unwrap_or_default();
+                let value = half_balance(&balance)?;
+                let mut msg = sibling_message(
+                    address.clone(), sibling_address(&address, &self.split_info)?, value.clone(), tr.now(), next_lt
+                );
+                msg.set_state_init(state_init);
+                if !account.sub_funds(&value)? {
+                    fail!(ExecutorError::phase_failed(TrPhase::Action, Some(&address), "cannot debit half of balance"))
+                }
+                let msg_cell: Cell = msg.write_to_new_cell()?.into();
+                let mut action = TrActionPhase::default();
+                action.success = true;
+                action.valid = true;
+                action.msgs_created = 1;
+                action.tot_msg_size.append(&msg_cell);
+                tr.add_out_message(&msg)?;
+                next_lt += 1;
+                description.action = Some(action);
+                description.aborted = false;
+            }
+            None => {
+                log::debug!(target: "executor", "account is not active, it cannot be split");
+                description.action = None;
+                description.aborted = true;
+            }
+        }
+
+        finish_transaction(
+            &mut tr, &mut account, account_root, old_hash, next_lt, &TransactionDescr::SplitPrepare(description)
+        )?;
+        Ok((tr, lt_reservation.commit(next_lt)))
+    }
+}
+
+/// Executor of split install transaction.
+/// Creates sibling account (which must not exist) with state and value
+/// sent to it by split prepare transaction.
+pub struct SplitInstallTransactionExecutor {
+    pub config: BlockchainConfig,
+    pub split_info: SplitMergeInfo,
+    pub prepare_transaction: Transaction,
+}
+
+impl SplitInstallTransactionExecutor {
+    pub fn new(config: BlockchainConfig, split_info: SplitMergeInfo, prepare_transaction: Transaction) -> Self {
+        Self {
+            config,
+            split_info,
+            prepare_transaction,
+        }
+    }
+}
+
+impl TransactionExecutor for SplitInstallTransactionExecutor {
+    ///
+    /// Create end execute split install transaction for account
+    fn execute_with_params(
+        &self,
+        in_msg: Option<&Message>,
+        account_root: &mut Cell,
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
+        if in_msg.is_some() {
+            fail!("Split install transaction must not have input message")
+        }
+        check_split_info(&self.split_info)?;
+        let old_hash = account_root.repr_hash();
+        let mut account = Account::construct_from(&mut account_root.clone().into())?;
+        if account != Account::AccountNone {
+            fail!(ExecutorError::invalid_account(account.get_addr(), "installed account already exists"))
+        }
+        let msg = prepare_message(&self.prepare_transaction, &self.split_info)?;
+        let (address, value, state_init) = match (msg.dst(), msg.get_value(), msg.state_init()) {
+            (Some(address), Some(value), Some(state_init)) => (address, value.clone(), state_init.clone()),
+            _ => fail!(ExecutorError::InvalidMessage(
+                "split prepare message must have destination, value and account state".to_string()
+            ))
+        };
+        // transaction is executed after split prepare one
+        let lt_reservation = TransactionLt::reserve(&params.last_tr_lt, 0, Some(&msg));
+        let lt = lt_reservation.start();
+        let mut tr = new_transaction(address.get_address(), &account, &params, lt);
+
+        account = Account::with_address_and_ballance(&address, &value);
+        account.activate(state_init);
+        // storage of installed account is paid since transaction time
+        account.set_last_paid(tr.now());
+
+        let mut description = TransactionDescrSplitInstall::default();
+        description.split_info = self.split_info.clone();
+        description.prepare_transaction = ChildCell::with_struct(&self.prepare_transaction)?;
+        description.installed = true;
+
+        finish_transaction(
+            &mut tr, &mut account, account_root, old_hash, lt + 1, &TransactionDescr::SplitInstall(description)
+        )?;
+        Ok((tr, lt_reservation.commit(lt + 1)))
+    }
+}
+
+/// Executor of merge prepare transaction.
+/// After storage phase account sends its whole balance to sibling account and is deleted,
+/// state of sibling account is kept by merge. Transaction of account deleted
+/// by storage phase is aborted.
+pub struct MergePrepareTransactionExecutor {
+    pub config: BlockchainConfig,
+    pub split_info: SplitMergeInfo,
+}
+
+impl MergePrepareTransactionExecutor {
+    pub fn new(config: BlockchainConfig, split_info: SplitMergeInfo) -> Self {
+        Self {
+            config,
+            split_info,
+        }
+    }
+}
+
+impl TransactionExecutor for MergePrepareTransactionExecutor {
+    ///
+    /// Create end execute merge prepare transaction for account
+    fn execute_with_params(
+        &self,
+        in_msg: Option<&Message>,
+        account_root: &mut Cell,
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
+        if in_msg.is_some() {
+            fail!("Merge prepare transaction must not have input message")
+        }
+        check_split_info(&self.split_info)?;
+        let old_hash = account_root.repr_hash();
+        let mut account = Account::construct_from(&mut account_root.clone().into())?;
+        let address = check_account_address(&account, &self.split_info)?;
+        let is_special = self.config.is_special_account(&address)?;
+        let lt_reservation = TransactionLt::reserve(
+            &params.last_tr_lt,
+            account.last_tr_time().unwrap_or_default(),
+            None
+        );
+        let lt = lt_reservation.start();
+        // lt for the next outbound message
+        let mut next_lt = lt + 1;
+        let mut tr = new_transaction(address.get_address(), &account, &params, lt);
+
+        let mut description = TransactionDescrMergePrepare::default();
+        description.split_info = self.split_info.clone();
+        description.storage_ph = match storage_phase(&mut account, &mut tr, &self.config, is_special)? {
+            Some(storage_ph) => storage_ph,
+            None => fail!("Problem with storage phase")
+        };
+        description.aborted = description.storage_ph.status_change == AccStatusChange::Deleted;
+        if description.aborted {
+            log::debug!(target: "executor", "account is deleted by storage phase, it cannot be merged");
+        } else {
+            let value = account.get_balance().cloned().unwrap_or_default();
+            let msg = sibling_message(
+                address.clone(), sibling_address(&address, &self.split_info)?, value, tr.now(), next_lt
+            );
+            tr.add_out_message(&msg)?;
+            next_lt += 1;
+            account = Account::AccountNone;
+        }
+
+        finish_transaction(
+            &mut tr, &mut account, account_root, old_hash, next_lt, &TransactionDescr::MergePrepare(description)
+        )?;
+        Ok((tr, lt_reservation.commit(next_lt)))
+    }
+}
+
+/// Executor of merge install transaction.
+/// Credits account with value sent to it by merge prepare transaction of sibling account,
+/// then runs storage phase. Compute and action phases are not performed.
+pub struct MergeInstallTransactionExecutor {
+    pub config: BlockchainConfig,
+    pub split_info: SplitMergeInfo,
+    pub prepare_transaction: Transaction,
+}
+
+impl MergeInstallTransactionExecutor {
+    pub fn new(config: BlockchainConfig, split_info: SplitMergeInfo, prepare_transaction: Transaction) -> Self {
+        Self {
+            config,
+            split_info,
+            prepare_transaction,
+        }
+    }
+}
+
+impl TransactionExecutor for MergeInstallTransactionExecutor {
+    ///
+    /// Create end execute merge install transaction for account
+    fn execute_with_params(
+        &self,
+        in_msg: Option<&Message>,
+        account_root: &mut Cell,
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
+        if in_msg.is_some() {
+            fail!("Merge install transaction must not have input message")
+        }
+        check_split_info(&self.split_info)?;
+        let old_hash = account_root.repr_hash();
+        let mut account = Account::construct_from(&mut account_root.clone().into())?;
+        let address = check_account_address(&account, &self.split_info)?;
+        let is_special = self.config.is_special_account(&address)?;
+        let msg = prepare_message(&self.prepare_transaction, &self.split_info)?;
+        // transaction is executed after merge prepare one
+        let lt_reservation = TransactionLt::reserve(
+            &params.last_tr_lt,
+            account.last_tr_time().unwrap_or_default(),
+            Some(&msg)
+        );
+        let lt = lt_reservation.start();
+        let mut tr = new_transaction(address.get_address(), &account, &params, lt);
+
+        let mut description = TransactionDescrMergeInstall::default();
+        description.split_info = self.split_info.clone();
+        description.prepare_transaction = ChildCell::with_struct(&self.prepare_transaction)?;
+        // merged value is credited first, so it can pay storage fees
+        description.credit_ph = credit_phase(&msg, &mut account)?;
+        description.storage_ph = storage_phase(&mut account, &mut tr, &self.config, is_special)?;
+        description.destroyed = description.storage_ph.as_ref()
+            .map(|phase| phase.status_change == AccStatusChange::Deleted)
+            .unwrap_or(false);
+        description.compute_ph = skipped_compute_phase();
+        description.action = None;
+        description.aborted = false;
+
+        finish_transaction(
+            &mut tr, &mut account, account_root, old_hash, lt + 1, &TransactionDescr::MergeInstall(description)
+        )?;
+        Ok((tr, lt_reservation.commit(lt + 1)))
+    }
+}
@@ -0,0 +1,95 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+
+use crate::{
+    blockchain_config::BlockchainConfig,
+    ExecuteParams, TransactionExecutor, TransactionLt,
+    tr_phases::storage_phase,
+};
+
+use ton_block::{
+    Account, Serializable, Deserializable, Message,
+    HashUpdate, Transaction, TransactionDescr,
+};
+use ton_types::{fail, Cell, Result};
+
+
+pub struct StorageTransactionExecutor {
+    pub config: BlockchainConfig,
+}
+
+impl StorageTransactionExecutor {
+    pub fn new(config: BlockchainConfig) -> Self {
+        Self {
+            config,
+        }
+    }
+}
+
+impl TransactionExecutor for StorageTransactionExecutor {
+    ///
+    /// Create end execute storage transaction for account
+    fn execute_with_params(
+        &self,
+        in_msg: Option<&Message>,
+        account_root: &mut Cell,
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
+        if in_msg.is_some() {
+            fail!("Storage transaction must not have input message")
+        }
+        let old_hash = account_root.repr_hash();
+        let mut account = Account::construct_from(&mut account_root.clone().into())?;
+        let account_id = match account.get_id() {
+            Some(addr) => addr,
+            None => fail!("Account should have Standard address")
+        };
+        let is_special = match account.get_addr() {
+            Some(addr) => self.config.is_special_account(addr)?,
+            None => false
+        };
+        let mut tr = Transaction::with_address_and_status(account_id, account.status());
+        let lt_reservation = TransactionLt::reserve(
+            &params.last_tr_lt,
+            account.last_tr_time().unwrap_or_default(),
+            None
+        );
+        let lt = lt_reservation.start();
+        tr.set_logical_time(lt);
+        tr.prev_trans_hash = params.prev_trans_hash;
+        tr.prev_trans_lt = params.prev_trans_lt;
+        tr.set_now(params.block_unixtime);
+
+        let description = match storage_phase(&mut account, &mut tr, &self.config, is_special)? {
+            Some(storage_ph) => TransactionDescr::Storage(storage_ph),
+            None => fail!("Problem with storage phase")
+        };
+
+        // transaction has no outbound messages
+        account.set_last_tr_time(lt + 1);
+        account.update_storage_stat()?;
+
+        tr.set_end_status(account.status());
+        *account_root = account.write_to_new_cell()?.into();
+
+        // calculate Hash update
+        log::debug!(target: "executor", "calculate Hash update");
+        let new_hash = account_root.repr_hash();
+        tr.write_state_update(&HashUpdate::with_hashes(old_hash, new_hash))?;
+        tr.write_description(&description)?;
+
+        Ok((tr, lt_reservation.commit(lt + 1)))
+    }
+}
@@ -0,0 +1,80 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ton_block::{ConfigParam12, SizeLimitsConfig, WorkchainDescr, Workchains};
+
+fn config_with_workchains(workchains: Workchains) -> BlockchainConfig {
+    let mut raw_config = ConfigParams::default();
+    raw_config.set_config(ConfigParamEnum::ConfigParam12(ConfigParam12 { workchains })).unwrap();
+    BlockchainConfig {
+        raw_config,
+        ..BlockchainConfig::default()
+    }
+}
+
+fn workchain_descr(active: bool, accept_msgs: bool) -> WorkchainDescr {
+    WorkchainDescr {
+        active,
+        accept_msgs,
+        ..WorkchainDescr::default()
+    }
+}
+
+#[test]
+fn test_only_base_workchain_is_accessible_without_workchains_config() {
+    let config = BlockchainConfig::default();
+    assert!(config.is_workchain_accessible(MASTERCHAIN_ID));
+    assert!(config.is_workchain_accessible(BASE_WORKCHAIN_ID));
+    assert!(!config.is_workchain_accessible(5));
+
+    let config = config_with_workchains(Workchains::default());
+    assert!(config.is_workchain_accessible(BASE_WORKCHAIN_ID));
+    assert!(!config.is_workchain_accessible(5));
+}
+
+#[test]
+fn test_disabled_workchain_is_not_accessible() {
+    let mut workchains = Workchains::default();
+    workchains.set(&BASE_WORKCHAIN_ID, &workchain_descr(true, true)).unwrap();
+    workchains.set(&5, &workchain_descr(false, true)).unwrap();
+    workchains.set(&6, &workchain_descr(true, false)).unwrap();
+    let config = config_with_workchains(workchains);
+
+    assert!(config.is_workchain_accessible(MASTERCHAIN_ID));
+    assert!(config.is_workchain_accessible(BASE_WORKCHAIN_ID));
+    assert!(!config.is_workchain_accessible(5));
+    assert!(!config.is_workchain_accessible(6));
+    assert!(!config.is_workchain_accessible(7));
+}
+
+#[test]
+fn test_msg_size_limits_are_taken_from_config() {
+    let limits = MsgSizeLimits::with_config(&ConfigParams::default());
+    assert_eq!(limits.max_msg_cells, MsgSizeLimits::default().max_msg_cells);
+    assert_eq!(limits.max_msg_bits, MsgSizeLimits::default().max_msg_bits);
+
+    let mut config = ConfigParams::default();
+    let size_limits = SizeLimitsConfig {
+        max_msg_bits: 1000,
+        max_msg_cells: 10,
+        ..SizeLimitsConfig::default()
+    };
+    config.set_config(ConfigParamEnum::ConfigParam43(size_limits)).unwrap();
+    let limits = MsgSizeLimits::with_config(&config);
+    assert_eq!(limits.max_msg_cells, 10);
+    assert_eq!(limits.max_msg_bits, 1000);
+    // message depth is not limited by config
+    assert_eq!(limits.max_msg_depth, MsgSizeLimits::default().max_msg_depth);
+}
@@ -0,0 +1,477 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+use super::*;
+use ton_block::AnycastInfo;
+use ton_types::SliceData;
+
+const BLOCK_UT: u32 = 1576526553;
+const ACCOUNT_BALANCE: u64 = 10_000_000_000;
+const MSG_VALUE: u64 = 1_000_000_000;
+
+fn address(byte: u8) -> MsgAddressInt {
+    MsgAddressInt::with_standart(None, 0, AccountId::from([byte; 32])).unwrap()
+}
+
+fn internal_message(src: MsgAddressIntOrNone, dst: MsgAddressInt, value: CurrencyCollection) -> Message {
+    let mut header = InternalMessageHeader::with_addresses(address(0x11), dst, value);
+    header.src = src;
+    header.ihr_disabled = true;
+    Message::with_int_header(header)
+}
+
+fn actions_cell(actions: Vec<OutAction>) -> Option<Cell> {
+    let mut list = OutActions::default();
+    for action in actions {
+        list.push_back(action);
+    }
+    Some(list.write_to_new_cell().unwrap().into())
+}
+
+fn run_action_phase(
+    acc: &mut Account,
+    actions: Vec<OutAction>,
+    msg_balance: &mut CurrencyCollection,
+) -> (Transaction, TrActionPhase) {
+    let mut tr = Transaction::with_address_and_status(acc.get_id().unwrap(), acc.status());
+    tr.set_now(BLOCK_UT);
+    let mut lt = 1;
+    let phase = action_phase(
+        &mut tr, acc, actions_cell(actions), msg_balance, &BlockchainConfig::default(), &mut lt, false
+    ).unwrap().unwrap();
+    (tr, phase)
+}
+
+fn out_messages(tr: &Transaction) -> Vec<Message> {
+    let mut msgs = vec![];
+    tr.iterate_out_msgs(|msg| {
+        msgs.push(msg);
+        Ok(true)
+    }).unwrap();
+    msgs
+}
+
+fn result_code(phase: &TrActionPhase) -> Option<ActionResultCode> {
+    ActionResultCode::from_i32(phase.result_code)
+}
+
+#[test]
+fn test_anycast_dest_addr_is_rewritten() {
+    let anycast = AnycastInfo::with_rewrite_pfx(SliceData::new(vec![0x5A, 0x80])).unwrap();
+    let dst = MsgAddressInt::with_standart(Some(anycast), 0, AccountId::from([0x22; 32])).unwrap();
+
+    let result = check_rewrite_dest_addr(&dst, &BlockchainConfig::default(), &address(0x11));
+
+    // first 8 bits are replaced by the prefix of account address
+    let mut expected = [0x22; 32];
+    expected[0] = 0x11;
+    let expected = MsgAddressInt::with_standart(None, 0, AccountId::from(expected)).unwrap();
+    assert_eq!(result, Ok(expected));
+}
+
+#[test]
+fn test_anycast_dest_addr_with_empty_prefix_is_rejected() {
+    // depth of anycast prefix must be in 1..=30
+    let anycast = AnycastInfo::default();
+    let dst = MsgAddressInt::with_standart(Some(anycast), 0, AccountId::from([0x22; 32])).unwrap();
+
+    let result = check_rewrite_dest_addr(&dst, &BlockchainConfig::default(), &address(0x11));
+
+    assert_eq!(result, Err(ActionResultCode::IncorrectDstAddress));
+}
+
+#[test]
+fn test_var_dest_addr_is_packed_to_std() {
+    let dst = MsgAddressInt::with_variant(None, 0, AccountId::from([0x22; 32])).unwrap();
+
+    let result = check_rewrite_dest_addr(&dst, &BlockchainConfig::default(), &address(0x11));
+
+    assert_eq!(result, Ok(address(0x22)));
+}
+
+#[test]
+fn test_dest_addr_in_inaccessible_workchain_is_rejected() {
+    let dst = MsgAddressInt::with_standart(None, 5, AccountId::from([0x22; 32])).unwrap();
+
+    let result = check_rewrite_dest_addr(&dst, &BlockchainConfig::default(), &address(0x11));
+
+    assert_eq!(result, Err(ActionResultCode::IncorrectDstAddress));
+}
+
+#[test]
+fn test_src_addr_is_replaced_or_checked() {
+    let myself = address(0x11);
+    assert_eq!(check_replace_src_addr(&MsgAddressIntOrNone::None, &myself), Some(myself.clone()));
+    assert_eq!(check_replace_src_addr(&MsgAddressIntOrNone::Some(myself.clone()), &myself), Some(myself.clone()));
+    assert_eq!(check_replace_src_addr(&MsgAddressIntOrNone::Some(address(0x33)), &myself), None);
+}
+
+#[test]
+fn test_send_message_with_foreign_src_fails() {
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &CurrencyCollection::with_grams(ACCOUNT_BALANCE));
+    let msg = internal_message(
+        MsgAddressIntOrNone::Some(address(0x33)), address(0x22), CurrencyCollection::with_grams(MSG_VALUE)
+    );
+
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(0, msg)], &mut CurrencyCollection::default());
+
+    assert!(!phase.success);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::IncorrectSrcAddress));
+    assert_eq!(phase.result_arg, Some(0));
+    assert!(out_messages(&tr).is_empty());
+}
+
+#[test]
+fn test_send_message_to_inaccessible_workchain_fails() {
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &CurrencyCollection::with_grams(ACCOUNT_BALANCE));
+    let dst = MsgAddressInt::with_standart(None, 5, AccountId::from([0x22; 32])).unwrap();
+    let msg = internal_message(MsgAddressIntOrNone::None, dst, CurrencyCollection::with_grams(MSG_VALUE));
+
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(0, msg)], &mut CurrencyCollection::default());
+
+    assert!(!phase.success);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::IncorrectDstAddress));
+    assert!(out_messages(&tr).is_empty());
+}
+
+#[test]
+fn test_send_message_with_anycast_dst_is_rewritten() {
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &CurrencyCollection::with_grams(ACCOUNT_BALANCE));
+    let anycast = AnycastInfo::with_rewrite_pfx(SliceData::new(vec![0x5A, 0x80])).unwrap();
+    let dst = MsgAddressInt::with_standart(Some(anycast), 0, AccountId::from([0x22; 32])).unwrap();
+    let msg = internal_message(MsgAddressIntOrNone::None, dst, CurrencyCollection::with_grams(MSG_VALUE));
+
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(0, msg)], &mut CurrencyCollection::default());
+
+    assert!(phase.success);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::Success));
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    let mut expected = [0x22; 32];
+    expected[0] = 0x11;
+    let expected = MsgAddressInt::with_standart(None, 0, AccountId::from(expected)).unwrap();
+    assert_eq!(msgs[0].dst(), Some(expected));
+    assert_eq!(msgs[0].src(), Some(address(0x11)));
+}
+
+fn account_with_grams(grams: u64) -> Account {
+    Account::with_address_and_ballance(&address(0x11), &CurrencyCollection::with_grams(grams))
+}
+
+fn grams_without_fees(value: u64, fees: &[&Grams]) -> Grams {
+    let mut result = Grams::from(value);
+    for fee in fees {
+        assert!(result.sub(fee).unwrap());
+    }
+    result
+}
+
+#[test]
+fn test_send_remaining_msg_balance() {
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let mut msg_balance = CurrencyCollection::with_grams(MSG_VALUE);
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+
+    let (tr, phase) = run_action_phase(
+        &mut acc, vec![OutAction::new_send(SENDMSG_REMAINING_MSG_BALANCE, msg)], &mut msg_balance
+    );
+
+    assert!(phase.success);
+    let fwd_fees = phase.total_fwd_fees.clone().unwrap();
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    // forwarding fees are paid from the sent value
+    assert_eq!(msgs[0].get_value().unwrap().grams, grams_without_fees(MSG_VALUE, &[&fwd_fees]));
+    assert_eq!(acc.get_balance().unwrap().grams, Grams::from(ACCOUNT_BALANCE - MSG_VALUE));
+    assert!(msg_balance.grams.is_zero());
+}
+
+#[test]
+fn test_send_remaining_msg_balance_paying_fee_separately() {
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let mut msg_balance = CurrencyCollection::with_grams(MSG_VALUE);
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+    let mode = SENDMSG_REMAINING_MSG_BALANCE | SENDMSG_PAY_FEE_SEPARATELY;
+
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(mode, msg)], &mut msg_balance);
+
+    assert!(phase.success);
+    let fwd_fees = phase.total_fwd_fees.clone().unwrap();
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    // forwarding fees are paid from account balance
+    assert_eq!(msgs[0].get_value().unwrap().grams, Grams::from(MSG_VALUE));
+    assert_eq!(acc.get_balance().unwrap().grams, grams_without_fees(ACCOUNT_BALANCE - MSG_VALUE, &[&fwd_fees]));
+    assert!(msg_balance.grams.is_zero());
+}
+
+#[test]
+fn test_send_remaining_msg_balance_with_ignore_error() {
+    // remaining inbound value is too small to pay forwarding fees
+    let mut msg_balance = CurrencyCollection::with_grams(1);
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let (tr, phase) = run_action_phase(
+        &mut acc, vec![OutAction::new_send(SENDMSG_REMAINING_MSG_BALANCE, msg.clone())], &mut msg_balance
+    );
+    assert!(!phase.success);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::NotEnoughGrams));
+    assert!(out_messages(&tr).is_empty());
+
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let mode = SENDMSG_REMAINING_MSG_BALANCE | SENDMSG_IGNORE_ERROR;
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(mode, msg)], &mut msg_balance);
+    assert!(phase.success);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::Success));
+    assert!(out_messages(&tr).is_empty());
+    assert_eq!(acc.get_balance().unwrap().grams, Grams::from(ACCOUNT_BALANCE));
+    // skipped message does not spend remaining inbound value
+    assert_eq!(msg_balance.grams, Grams::from(1));
+}
+
+#[test]
+fn test_remaining_msg_balance_is_sent_only_once() {
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let mut msg_balance = CurrencyCollection::with_grams(MSG_VALUE);
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+    let mode = SENDMSG_REMAINING_MSG_BALANCE | SENDMSG_PAY_FEE_SEPARATELY;
+
+    let (tr, phase) = run_action_phase(
+        &mut acc,
+        vec![OutAction::new_send(mode, msg.clone()), OutAction::new_send(mode, msg)],
+        &mut msg_balance
+    );
+
+    assert!(phase.success);
+    let fwd_fees = phase.total_fwd_fees.clone().unwrap();
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 2);
+    assert_eq!(msgs[0].get_value().unwrap().grams, Grams::from(MSG_VALUE));
+    assert!(msgs[1].get_value().unwrap().grams.is_zero());
+    assert_eq!(acc.get_balance().unwrap().grams, grams_without_fees(ACCOUNT_BALANCE - MSG_VALUE, &[&fwd_fees]));
+    assert!(msg_balance.grams.is_zero());
+}
+
+/// Body of message which is deeper than allowed by default size limits
+fn too_deep_body() -> SliceData {
+    let mut cell = Cell::default();
+    for _ in 0..=BlockchainConfig::default().get_msg_size_limits().max_msg_depth {
+        let mut builder = BuilderData::new();
+        builder.append_reference_cell(cell);
+        cell = builder.into();
+    }
+    cell.into()
+}
+
+#[test]
+fn test_send_all_balance_and_delete_account() {
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+    let mode = SENDMSG_ALL_BALANCE | SENDMSG_DELETE_IF_EMPTY;
+
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(mode, msg)], &mut CurrencyCollection::default());
+
+    assert!(phase.success);
+    assert_eq!(phase.status_change, AccStatusChange::Deleted);
+    let fwd_fees = phase.total_fwd_fees.clone().unwrap();
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(msgs[0].get_value().unwrap().grams, grams_without_fees(ACCOUNT_BALANCE, &[&fwd_fees]));
+    assert!(acc.get_balance().unwrap().grams.is_zero());
+}
+
+#[test]
+fn test_account_with_reserved_balance_is_not_deleted() {
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+    let mode = SENDMSG_ALL_BALANCE | SENDMSG_DELETE_IF_EMPTY;
+
+    let (tr, phase) = run_action_phase(
+        &mut acc,
+        vec![
+            OutAction::new_reserve(0, CurrencyCollection::with_grams(MSG_VALUE)),
+            OutAction::new_send(mode, msg),
+        ],
+        &mut CurrencyCollection::default()
+    );
+
+    assert!(phase.success);
+    assert_eq!(phase.status_change, AccStatusChange::Unchanged);
+    assert_eq!(out_messages(&tr).len(), 1);
+    assert_eq!(acc.get_balance().unwrap().grams, Grams::from(MSG_VALUE));
+}
+
+#[test]
+fn test_skipped_message_does_not_delete_account() {
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let mut too_large_msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+    too_large_msg.set_body(too_deep_body());
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::default());
+
+    let (tr, phase) = run_action_phase(
+        &mut acc,
+        vec![
+            OutAction::new_send(SENDMSG_ALL_BALANCE | SENDMSG_DELETE_IF_EMPTY | SENDMSG_IGNORE_ERROR, too_large_msg),
+            OutAction::new_send(SENDMSG_ALL_BALANCE, msg),
+        ],
+        &mut CurrencyCollection::default()
+    );
+
+    assert!(phase.success);
+    // balance is empty, but the only message allowing deletion was not sent
+    assert_eq!(phase.status_change, AccStatusChange::Unchanged);
+    assert_eq!(out_messages(&tr).len(), 1);
+    assert!(acc.get_balance().unwrap().grams.is_zero());
+}
+
+const EXTRA_ID: u32 = 1;
+
+fn currencies(grams: u64, extra: u128) -> CurrencyCollection {
+    let mut value = CurrencyCollection::with_grams(grams);
+    value.set_other(EXTRA_ID, extra).unwrap();
+    value
+}
+
+fn extra(value: &CurrencyCollection) -> VarUInteger32 {
+    value.other.get(&EXTRA_ID).unwrap().unwrap_or_default()
+}
+
+fn extra_amount(amount: u128) -> VarUInteger32 {
+    extra(&currencies(0, amount))
+}
+
+#[test]
+fn test_check_extra_currencies() {
+    let balance = currencies(ACCOUNT_BALANCE, 100);
+    assert!(check_extra_currencies(&balance, &CurrencyCollection::with_grams(MSG_VALUE)));
+    assert!(check_extra_currencies(&balance, &currencies(0, 100)));
+    assert!(!check_extra_currencies(&balance, &currencies(0, 101)));
+
+    let mut other_currency = CurrencyCollection::default();
+    other_currency.set_other(EXTRA_ID + 1, 1).unwrap();
+    assert!(!check_extra_currencies(&balance, &other_currency));
+}
+
+#[test]
+fn test_send_message_with_extra_currencies() {
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &currencies(ACCOUNT_BALANCE, 100));
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), currencies(MSG_VALUE, 40));
+
+    let (tr, phase) = run_action_phase(
+        &mut acc, vec![OutAction::new_send(SENDMSG_PAY_FEE_SEPARATELY, msg)], &mut CurrencyCollection::default()
+    );
+
+    assert!(phase.success);
+    let fwd_fees = phase.total_fwd_fees.clone().unwrap();
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    assert_eq!(extra(msgs[0].get_value().unwrap()), extra_amount(40));
+    // extra currencies of sent message are debited from account with grams
+    let balance = acc.get_balance().unwrap();
+    assert_eq!(extra(balance), extra_amount(60));
+    assert_eq!(balance.grams, grams_without_fees(ACCOUNT_BALANCE - MSG_VALUE, &[&fwd_fees]));
+}
+
+#[test]
+fn test_send_message_with_not_enough_extra_currencies_fails() {
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &currencies(ACCOUNT_BALANCE, 100));
+    let msg = internal_message(MsgAddressIntOrNone::None, address(0x22), currencies(MSG_VALUE, 101));
+
+    let (tr, phase) = run_action_phase(&mut acc, vec![OutAction::new_send(0, msg)], &mut CurrencyCollection::default());
+
+    assert!(!phase.success);
+    assert!(phase.no_funds);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::NotEnoughExtra));
+    assert!(out_messages(&tr).is_empty());
+    assert_eq!(extra(acc.get_balance().unwrap()), extra_amount(100));
+}
+
+#[test]
+fn test_reserve_not_enough_extra_currencies() {
+    let mut remaining = currencies(ACCOUNT_BALANCE, 100);
+    let result = reserve_action_handler(0, &currencies(MSG_VALUE, 101), &mut remaining);
+    assert_eq!(result.err(), Some(ActionResultCode::NotEnoughExtra));
+    assert_eq!(extra(&remaining), extra_amount(100));
+}
+
+#[test]
+fn test_reserve_with_ignore_error_is_clamped_to_balance() {
+    let value = currencies(ACCOUNT_BALANCE + 1, 50);
+    let balance = currencies(ACCOUNT_BALANCE, 100);
+    let min = min_currencies(&value, &balance).unwrap();
+    assert_eq!(min.grams, Grams::from(ACCOUNT_BALANCE));
+    assert_eq!(extra(&min), extra_amount(50));
+
+    // every currency is reserved as much as balance has
+    let mut remaining = currencies(MSG_VALUE, 100);
+    let reserved = reserve_action_handler(RESERVE_IGNORE_ERROR, &currencies(0, 150), &mut remaining).unwrap();
+    assert!(reserved.grams.is_zero());
+    assert_eq!(extra(&reserved), extra_amount(100));
+    assert_eq!(remaining.grams, Grams::from(MSG_VALUE));
+    assert_eq!(extra(&remaining), extra_amount(0));
+}
+
+fn run_storage_phase(acc: &mut Account) -> TrStoragePhase {
+    let mut tr = Transaction::with_address_and_status(acc.get_id().unwrap(), acc.status());
+    tr.set_now(BLOCK_UT);
+    storage_phase(acc, &mut tr, &BlockchainConfig::default(), false).unwrap().unwrap()
+}
+
+#[test]
+fn test_uninit_account_without_funds_is_deleted_by_storage_phase() {
+    // storage was never paid, so account cannot pay storage fees
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &CurrencyCollection::default());
+    acc.update_storage_stat().unwrap();
+
+    let phase = run_storage_phase(&mut acc);
+
+    assert_eq!(phase.status_change, AccStatusChange::Deleted);
+    assert_eq!(acc, Account::AccountNone);
+}
+
+#[test]
+fn test_account_with_extra_currencies_is_not_deleted_by_storage_phase() {
+    let mut acc = Account::with_address_and_ballance(&address(0x11), &currencies(0, 100));
+    acc.update_storage_stat().unwrap();
+
+    let phase = run_storage_phase(&mut acc);
+
+    assert_eq!(phase.status_change, AccStatusChange::Unchanged);
+    assert!(phase.storage_fees_due.is_some());
+    assert_eq!(extra(acc.get_balance().unwrap()), extra_amount(100));
+    assert!(acc.storage_info().unwrap().due_payment.is_some());
+}
+
+#[test]
+fn test_send_too_large_message_fails() {
+    let mut msg = internal_message(MsgAddressIntOrNone::None, address(0x22), CurrencyCollection::with_grams(MSG_VALUE));
+    msg.set_body(too_deep_body());
+
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let (tr, phase) = run_action_phase(
+        &mut acc, vec![OutAction::new_send(0, msg.clone())], &mut CurrencyCollection::default()
+    );
+    assert!(!phase.success);
+    assert_eq!(result_code(&phase), Some(ActionResultCode::MsgTooLarge));
+    assert!(out_messages(&tr).is_empty());
+
+    let mut acc = account_with_grams(ACCOUNT_BALANCE);
+    let (tr, phase) = run_action_phase(
+        &mut acc, vec![OutAction::new_send(SENDMSG_IGNORE_ERROR, msg)], &mut CurrencyCollection::default()
+    );
+    assert!(phase.success);
+    assert!(out_messages(&tr).is_empty());
+    assert_eq!(acc.get_balance().unwrap().grams, Grams::from(ACCOUNT_BALANCE));
+}
@@ -15,32 +15,70 @@
 
 use crate::{
     blockchain_config::BlockchainConfig,
-    TransactionExecutor,
-    tr_phases::{compute_phase, storage_phase, action_phase}
+    ExecuteParams, StackBuilder, TransactionExecutor, TransactionLt,
+    tr_phases::{compute_phase, charge_gas_fees, storage_phase, action_phase, ComputePhaseParams}
 };
 
-use std::{sync::{atomic::{AtomicU64, Ordering}, Arc}};
 use ton_block::{
-    AddSub, CurrencyCollection, TransactionTickTock,
+    AccStatusChange, AddSub, CurrencyCollection, TransactionTickTock,
     Account, Serializable, Deserializable, Message,
     HashUpdate, Transaction, TrComputePhase, TransactionDescrTickTock, TransactionDescr
 };
-use ton_types::{fail, Cell, Result, UInt256};
+use ton_types::{fail, Cell, Result};
 use ton_vm::{
     int, boolean, stack::{Stack, StackItem, integer::IntegerData}
 };
 
 
+/// Builds standard initial stack of tick tock transaction:
+/// account balance, account address, tock flag and function selector -2.
+#[derive(Clone, Debug)]
+pub struct TickTockStackBuilder {
+    pub tt: TransactionTickTock,
+}
+
+impl TickTockStackBuilder {
+    pub fn new(tt: TransactionTickTock) -> Self {
+        Self { tt }
+    }
+}
+
+impl StackBuilder for TickTockStackBuilder {
+    fn build_stack(&self, _in_msg: Option<&Message>, account: &Account) -> Result<Stack> {
+        let account_balance = account.get_balance().map(|balance| balance.grams.clone()).unwrap_or_default();
+        let account_id = account.get_id().unwrap_or_default();
+        let mut stack = Stack::new();
+        stack
+            .push(int!(account_balance.0.clone()))
+            .push(int!(account_id.clone().get_bigint(256)))
+            .push(boolean!(self.tt.is_tock()))
+            .push(int!(-2));
+        Ok(stack)
+    }
+}
+
 pub struct TickTockTransactionExecutor {
     pub config: BlockchainConfig,
     pub tt: TransactionTickTock,
+    pub stack_builder: Box<dyn StackBuilder>,
 }
 
 impl TickTockTransactionExecutor {
     pub fn new(config: BlockchainConfig, tt: TransactionTickTock) -> Self {
+        let stack_builder = Box::new(TickTockStackBuilder::new(tt.clone()));
+        Self::with_stack_builder(config, tt, stack_builder)
+    }
+
+    /// Create executor which uses custom builder of VM stack and contract info
+    pub fn with_stack_builder(
+        config: BlockchainConfig,
+        tt: TransactionTickTock,
+        stack_builder: Box<dyn StackBuilder>
+    ) -> Self {
         Self {
             config,
             tt,
+            stack_builder,
         }
     }
 }
@@ -48,15 +86,12 @@ impl TickTockTransactionExecutor {
 impl TransactionExecutor for TickTockTransactionExecutor {
     ///
     /// Create end execute transaction from message for account
-    fn execute(
+    fn execute_with_params(
         &self,
         in_msg: Option<&Message>,
         account_root: &mut Cell,
-        block_unixtime: u32,
-        block_lt: u64,
-        last_tr_lt: Arc<AtomicU64>,
-        debug: bool
-    ) -> Result<Transaction> {
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)> {
         if in_msg.is_some() {
             fail!("Tick Tock transaction must not have input message")
         }
@@ -75,31 +110,52 @@ impl TransactionExecutor for TickTockTransactionExecutor {
         };
         let is_special = true;
         let mut tr = Transaction::with_address_and_status(account_id.clone(), account.status());
-        let lt = last_tr_lt.fetch_add(1, Ordering::SeqCst);
-        tr.prev_trans_hash = UInt256::from([0;32]); // TODO: prev trans hash
-        tr.set_now(block_unixtime);
+        let lt_reservation = TransactionLt::reserve(
+            &params.last_tr_lt,
+            account.last_tr_time().unwrap_or_default(),
+            None
+        );
+        let lt = lt_reservation.start();
+        // lt for the next outbound message
+        let mut next_lt = lt + 1;
+        tr.set_logical_time(lt);
+        tr.prev_trans_hash = params.prev_trans_hash;
+        tr.prev_trans_lt = params.prev_trans_lt;
+        tr.set_now(params.block_unixtime);
 
         let mut description = TransactionDescrTickTock::default();
         description.tt = self.tt.clone();
 
         // TODO: add and process ihr_delivered parameter (if ihr_delivered ihr_fee is added to total fees)
-        // TODO: add msg_balance_remaining variable and use it in phases 
 
-        description.storage = match storage_phase(&mut account, &mut tr, &self.config, is_special) {
+        description.storage = match storage_phase(&mut account, &mut tr, &self.config, is_special)? {
             Some(storage_ph) => storage_ph,
             None => fail!("Problem with storage phase")
         };
+        description.destroyed = description.storage.status_change == AccStatusChange::Deleted;
+
+        // tick tock transaction has no inbound message, so there is no message value
+        let mut msg_balance = CurrencyCollection::default();
+        // account state after storage phase, it is restored if transaction is aborted
+        let account_before_compute = account.clone();
 
-        let smci = self.build_contract_info(&account, &account_address, block_unixtime, block_lt, lt); 
+        let smci = self.stack_builder.build_contract_info(
+            &self.config, &account, &account_address, lt, &params
+        );
         log::debug!(target: "executor", "compute_phase");
+        let mut compute_params = ComputePhaseParams {
+            gas_config: self.config.get_gas_config(&account_address),
+            is_special,
+            block_gas: params.block_gas.as_deref().map(|block_gas| block_gas.reservation()),
+            debug: params.debug,
+        };
         let (compute_ph, actions) = compute_phase(
             None, 
             &mut account, 
+            &mut msg_balance,
             &smci, 
-            self,
-            self.config.get_gas_config(&account_address),
-            is_special,
-            debug
+            self.stack_builder.as_ref(),
+            &mut compute_params,
         )?;
         description.compute_ph = compute_ph;
         description.action = match description.compute_ph {
@@ -108,7 +164,7 @@ impl TransactionExecutor for TickTockTransactionExecutor {
                 if phase.success {
                     log::debug!(target: "executor", "compute_phase: TrComputePhase::Vm success");
                     log::debug!(target: "executor", "action_phase");
-                    action_phase(&mut tr, &mut account, actions, &self.config, last_tr_lt.clone(), is_special)
+                    action_phase(&mut tr, &mut account, actions, &mut msg_balance, &self.config, &mut next_lt, is_special)?
                 } else {
                     log::debug!(target: "executor", "compute_phase: TrComputePhase::Vm failed");
                     None
@@ -134,30 +190,44 @@ impl TransactionExecutor for TickTockTransactionExecutor {
         };
         
         log::debug!(target: "executor", "Desciption.aborted {}", description.aborted);
-        account.set_last_tr_time(lt);
 
-        if !description.aborted {
-            tr.set_end_status(account.status());
-            *account_root = account.write_to_new_cell()?.into();
-
-            // calculate Hash update
-            log::debug!(target: "executor", "calculate Hash update");
-            let new_hash = account_root.repr_hash();
-            tr.write_state_update(&HashUpdate::with_hashes(old_hash, new_hash))?;
+        if description.aborted {
+            // changes of compute and action phases are rolled back, but gas fees must be paid
+            account = account_before_compute;
+            next_lt = lt + 1;
+            if let TrComputePhase::Vm(ref mut phase) = description.compute_ph {
+                charge_gas_fees(&mut tr, &mut account, phase)?;
+            }
+        } else {
+            let deleted = description.action.as_ref()
+                .map(|phase| phase.status_change == AccStatusChange::Deleted)
+                .unwrap_or(false);
+            if deleted {
+                log::debug!(target: "executor", "account is deleted");
+                account = Account::AccountNone;
+                description.destroyed = true;
+            }
         }
+        // account keeps end lt of its last transaction
+        account.set_last_tr_time(next_lt);
+        // code, data and libraries could be changed, so storage usage must be recalculated
+        account.update_storage_stat()?;
+
+        tr.set_end_status(account.status());
+        *account_root = account.write_to_new_cell()?.into();
+
+        // calculate Hash update
+        log::debug!(target: "executor", "calculate Hash update");
+        let new_hash = account_root.repr_hash();
+        tr.write_state_update(&HashUpdate::with_hashes(old_hash, new_hash))?;
+        let gas_used = match description.compute_ph {
+            TrComputePhase::Vm(ref phase) => phase.gas_used.0,
+            TrComputePhase::Skipped(_) => 0,
+        };
         tr.write_description(&TransactionDescr::TickTock(description))?;
+        // transaction is executed, so gas it has used is taken from block
+        compute_params.commit_block_gas(gas_used);
 
-        Ok(tr)
-    }
-    fn build_stack(&self, _in_msg: Option<&Message>, account: &Account) -> Stack {
-        let account_balance = account.get_balance().map(|balance| balance.grams.clone()).unwrap_or_default();
-        let account_id = account.get_id().unwrap_or_default();
-        let mut stack = Stack::new();
-        stack
-            .push(int!(account_balance.0.clone()))
-            .push(int!(account_id.clone().get_bigint(256)))
-            .push(boolean!(self.tt.is_tock()))
-            .push(int!(-2));
-        stack
+        Ok((tr, lt_reservation.commit(next_lt)))
     }
 }
@@ -14,61 +14,171 @@
 
 use crate::{
     blockchain_config::{BlockchainConfig, GasConfigFull, CalcMsgFwdFees}, 
-    error::ExecutorError, vmsetup::VMSetup,
-    transaction_executor::TransactionExecutor,
+    error::{ExecutorError, ExtMsgRejectReason, TrPhase}, vmsetup::VMSetup,
+    transaction_executor::{BlockGasReservation, StackBuilder},
 };
 
 use num_traits::ToPrimitive;
-use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+use std::sync::Arc;
 use ton_block::{
     Deserializable, GetRepresentationHash, Serializable,
-    Account, AccountState, MsgForwardPrices,
+    Account, AccountState, GlobalCapabilities,
     AddSub, CommonMsgInfo, CurrencyCollection, InternalMessageHeader, 
     Message, MsgAddressInt, MsgAddressIntOrNone,
-    OutAction, OutActions, RESERVE_ALL_BUT, RESERVE_IGNORE_ERROR, RESERVE_VALID_MODES,
-    SENDMSG_ALL_BALANCE, SENDMSG_IGNORE_ERROR, SENDMSG_PAY_FEE_SEPARATELY,
+    OutAction, OutActions, SET_LIB_CODE_ADD_PUBLIC, SET_LIB_CODE_REMOVE, RESERVE_ALL_BUT, RESERVE_IGNORE_ERROR, RESERVE_VALID_MODES,
+    SENDMSG_ALL_BALANCE, SENDMSG_DELETE_IF_EMPTY, SENDMSG_IGNORE_ERROR, SENDMSG_PAY_FEE_SEPARATELY,
     SENDMSG_REMAINING_MSG_BALANCE, SENDMSG_VALID_FLAGS,
     AccStatusChange, ComputeSkipReason, Transaction, TrActionPhase, 
     TrBouncePhase, TrBouncePhaseOk, TrBouncePhaseNofunds, TrComputePhase,
     TrComputePhaseSkipped, TrComputePhaseVm, TrCreditPhase,
-    TrStoragePhase,
-    Grams, VarUInteger7,
+    TrStoragePhase, StorageUsedShort,
+    Grams, VarUInteger7, VarUInteger32, MASTERCHAIN_ID,
 };
-use ton_types::{Cell, error, fail, Result};
+use ton_types::{AccountId, BuilderData, Cell, error, fail, Result, UInt256};
 use ton_vm::{
     error::TvmError, executor::gas::gas_state::Gas,
     smart_contract_info::SmartContractInfo, stack::StackItem
 };
 
 
-const RESULT_CODE_ACTIONLIST_INVALID: i32 = 32;
-const RESULT_CODE_TOO_MANY_ACTIONS:   i32 = 33;
-const RESULT_CODE_UNKNOWN_ACTION:     i32 = 34;
-const RESULT_CODE_NOT_ENOUGH_GRAMS:   i32 = 37;
-const RESULT_CODE_INVALID_BALANCE:    i32 = 40;
-const RESULT_CODE_BAD_ACCOUNT_STATE:  i32 = 41;
-const RESULT_CODE_UNSUPPORTED:        i32 = -1;
-const MAX_ACTIONS: usize = 255;
+/// Result codes of action phase (`TrActionPhase::result_code`).
+/// Codes of TVM exceptions (see `exit_code_description`) do not intersect with them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ActionResultCode {
+    Success = 0,
+    ActionListInvalid = 32,
+    TooManyActions = 33,
+    UnknownAction = 34,
+    IncorrectSrcAddress = 35,
+    IncorrectDstAddress = 36,
+    NotEnoughGrams = 37,
+    NotEnoughExtra = 38,
+    MsgTooLarge = 39,
+    InvalidBalance = 40,
+    BadAccountState = 41,
+    Unsupported = -1,
+}
+
+impl ActionResultCode {
+    /// Convert code from `TrActionPhase::result_code`, returns None for unknown code
+    pub fn from_i32(code: i32) -> Option<Self> {
+        Some(match code {
+            0 => ActionResultCode::Success,
+            32 => ActionResultCode::ActionListInvalid,
+            33 => ActionResultCode::TooManyActions,
+            34 => ActionResultCode::UnknownAction,
+            35 => ActionResultCode::IncorrectSrcAddress,
+            36 => ActionResultCode::IncorrectDstAddress,
+            37 => ActionResultCode::NotEnoughGrams,
+            38 => ActionResultCode::NotEnoughExtra,
+            39 => ActionResultCode::MsgTooLarge,
+            40 => ActionResultCode::InvalidBalance,
+            41 => ActionResultCode::BadAccountState,
+            -1 => ActionResultCode::Unsupported,
+            _ => return None
+        })
+    }
+
+    pub fn as_i32(self) -> i32 {
+        self as i32
+    }
+
+    pub fn is_success(self) -> bool {
+        self == ActionResultCode::Success
+    }
+
+    /// Check if action failed because account has not enough funds
+    pub fn is_no_funds(self) -> bool {
+        self == ActionResultCode::NotEnoughGrams || self == ActionResultCode::NotEnoughExtra
+    }
+
+    pub fn description(self) -> &'static str {
+        match self {
+            ActionResultCode::Success => "success",
+            ActionResultCode::ActionListInvalid => "action list is invalid",
+            ActionResultCode::TooManyActions => "too many actions",
+            ActionResultCode::UnknownAction => "unknown action",
+            ActionResultCode::IncorrectSrcAddress => "incorrect source address of outbound message",
+            ActionResultCode::IncorrectDstAddress => "incorrect destination address of outbound message",
+            ActionResultCode::NotEnoughGrams => "not enough grams",
+            ActionResultCode::NotEnoughExtra => "not enough extra currencies",
+            ActionResultCode::MsgTooLarge => "outbound message is too large",
+            ActionResultCode::InvalidBalance => "invalid balance",
+            ActionResultCode::BadAccountState => "bad account state",
+            ActionResultCode::Unsupported => "unsupported action or mode",
+        }
+    }
+}
+
+impl From<ActionResultCode> for i32 {
+    fn from(code: ActionResultCode) -> i32 {
+        code.as_i32()
+    }
+}
+
+impl std::convert::TryFrom<i32> for ActionResultCode {
+    type Error = i32;
+    fn try_from(code: i32) -> std::result::Result<Self, i32> {
+        ActionResultCode::from_i32(code).ok_or(code)
+    }
+}
+
+impl std::fmt::Display for ActionResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.description(), self.as_i32())
+    }
+}
+
+/// Returns description of exit code of compute phase (`TrComputePhaseVm::exit_code`).
+/// Standard TVM exceptions have codes 2..=13, codes 0 and 1 mean success,
+/// other codes are thrown by contract itself.
+pub fn exit_code_description(exit_code: i32) -> &'static str {
+    match exit_code {
+        0 | 1 => "success",
+        2 => "stack underflow",
+        3 => "stack overflow",
+        4 => "integer overflow",
+        5 => "range check error",
+        6 => "invalid opcode",
+        7 => "type check error",
+        8 => "cell overflow",
+        9 => "cell underflow",
+        10 => "dictionary error",
+        11 => "unknown error",
+        12 => "fatal error",
+        13 => "out of gas",
+        -1 => "unknown VM error",
+        _ => "exception thrown by contract",
+    }
+}
 
-// #[allow(dead_code)]
-// const MAX_MSG_CELLS: usize = 1 << 13;
-// #[allow(dead_code)]
-// const MAX_MSG_BITS: usize = 1 << 21;
+pub(crate) const MAX_ACTIONS: usize = 255;
 
 pub const MINIMAL_FEE: u64 = 1; //1 nanogram
 
 /// Implementation of transaction's storage phase.
 /// If account does not exist - phase skipped.
-/// Calculates storage fees and substracts them from account balance.
-/// If account balance becomes negative after that, then account is frozen.
+/// Calculates storage fees since last payment and substracts them from account balance.
+/// Time of last payment is set to transaction time.
+/// If account balance is not enough to pay storage fees, unpaid fees are accumulated
+/// in account's due payment. Account is frozen if due payment exceeds `freeze_due_limit`
+/// and deleted if due payment exceeds `delete_due_limit`. Uninitialized account
+/// which cannot pay storage fees is deleted at once. Account holding extra currencies
+/// is never deleted, so they are not lost.
 pub fn storage_phase(
     acc: &mut Account,
     tr: &mut Transaction,
     config: &BlockchainConfig,
     is_special: bool
-) -> Option<TrStoragePhase> {
+) -> Result<Option<TrStoragePhase>> {
     log::debug!(target: "executor", "storage_phase");
-    let balance = acc.get_balance()?.clone();
+    let balance = match acc.get_balance() {
+        Some(balance) => balance.clone(),
+        None => return Ok(None)
+    };
+    let address = acc.get_addr().cloned()
+        .ok_or_else(|| ExecutorError::invalid_account(None, "account has no address"))?;
+    let phase_error = |cause: failure::Error| ExecutorError::phase_failed(TrPhase::Storage, Some(&address), cause);
 
     let mut fee;
     if is_special {
@@ -78,26 +188,50 @@ pub fn storage_phase(
             .storage_info()
             .and_then(|info| info.due_payment.clone())
             .unwrap_or(Grams::zero());
-        fee.add(&config.calc_storage_fee(
-            acc.storage_info()?,
-            acc.get_addr()?,
-            tr.now(),
-        ).into()).unwrap();
+        let storage_info = acc.storage_info()
+            .ok_or_else(|| ExecutorError::invalid_account(Some(&address), "account has no storage info"))?;
+        let storage_fee = config.calc_storage_fee(storage_info, &address, tr.now()).map_err(phase_error)?;
+        fee.add(&storage_fee.into())
+            .map_err(phase_error)?;
     }
+    //storage is paid (or the debt is fixed in due payment) up to the transaction time
+    acc.set_last_paid(tr.now());
 
     if balance.grams >= fee {
-        assert_eq!(acc.sub_funds(&CurrencyCollection::from_grams(fee.clone())).unwrap(), true, "all checks have been done");
-        tr.total_fees_mut().add(&CurrencyCollection::from_grams(fee.clone())).unwrap();
+        if !acc.sub_funds(&CurrencyCollection::from_grams(fee.clone())).map_err(phase_error)? {
+            fail!(ExecutorError::phase_failed(TrPhase::Storage, Some(&address), "cannot collect storage fee"))
+        }
+        tr.total_fees_mut().add(&CurrencyCollection::from_grams(fee.clone())).map_err(phase_error)?;
+        acc.set_due_payment(None);
         log::debug!(target: "executor", "AccStatusChange::Unchanged");
-        Some(TrStoragePhase::with_params(fee, None, AccStatusChange::Unchanged))
+        Ok(Some(TrStoragePhase::with_params(fee, None, AccStatusChange::Unchanged)))
     } else {
-        fee.sub(&balance.grams).unwrap();
+        fee.sub(&balance.grams).map_err(phase_error)?;
         let collected = balance.grams.clone();
-        assert_eq!(acc.sub_funds(&CurrencyCollection::from_grams(balance.grams)).unwrap(), true, "all checks have been done");
-        acc.freeze_account();
-        tr.total_fees_mut().add(&CurrencyCollection::from_grams(collected.clone())).unwrap();
-        log::debug!(target: "executor", "AccStatusChange::Frozen");
-        Some(TrStoragePhase::with_params(collected, Some(fee), AccStatusChange::Frozen))
+        if !acc.sub_funds(&CurrencyCollection::from_grams(balance.grams)).map_err(phase_error)? {
+            fail!(ExecutorError::phase_failed(TrPhase::Storage, Some(&address), "cannot collect account balance"))
+        }
+        tr.total_fees_mut().add(&CurrencyCollection::from_grams(collected.clone())).map_err(phase_error)?;
+
+        let gas_config = config.get_gas_config(&address);
+        let is_uninit = matches!(acc.state(), Some(AccountState::AccountUninit));
+        let is_active = matches!(acc.state(), Some(AccountState::AccountActive(_)));
+        let is_empty = is_empty_balance(acc.get_balance().unwrap_or(&CurrencyCollection::default()))
+            .map_err(phase_error)?;
+        let status_change = if is_empty && (is_uninit || fee > Grams::from(gas_config.delete_due_limit)) {
+            log::debug!(target: "executor", "AccStatusChange::Deleted");
+            *acc = Account::AccountNone;
+            AccStatusChange::Deleted
+        } else if is_active && fee > Grams::from(gas_config.freeze_due_limit) {
+            log::debug!(target: "executor", "AccStatusChange::Frozen");
+            acc.freeze_account();
+            AccStatusChange::Frozen
+        } else {
+            log::debug!(target: "executor", "AccStatusChange::Unchanged");
+            AccStatusChange::Unchanged
+        };
+        acc.set_due_payment(Some(fee.clone()));
+        Ok(Some(TrStoragePhase::with_params(collected, Some(fee), status_change)))
     }
 }
 
@@ -105,98 +239,191 @@ pub fn storage_phase(
 /// Increases account balance by the amount that appears in the internal message header.
 /// If account does not exist - phase skipped.
 /// If message is not internal - phase skipped.
-pub fn credit_phase(msg: &Message, acc: &mut Account) -> Option<TrCreditPhase> {
+pub fn credit_phase(msg: &Message, acc: &mut Account) -> Result<Option<TrCreditPhase>> {
     log::debug!(target: "executor", "credit_phase");
     if Account::AccountNone == *acc {
         log::debug!(target: "executor", " Account::AccountNone");
-        return None;
+        return Ok(None);
+    }
+    match msg.get_value() {
+        Some(value) => {
+            acc.add_funds(value).map_err(|err| 
+                ExecutorError::phase_failed(TrPhase::Credit, acc.get_addr(), err)
+            )?;
+            Ok(Some(TrCreditPhase::with_params(None, value.clone())))
+        }
+        None => Ok(None)
     }
-    msg.get_value().and_then(|value| {
-        acc.add_funds(value).unwrap();
-        Some(TrCreditPhase::with_params(None, value.clone()))
-    })
     //TODO: Is it need to credit with ihr_fee value in internal messages?
 }
 
-pub fn init_gas(acc_balance: u128, msg_balance: u128, is_external: bool, is_special: bool, gas_info: &GasConfigFull) -> Gas {
+/// Calculates gas limits of computing phase.
+/// Gas of ordinary account is limited by its balance and by remaining block gas,
+/// the gas is reserved in block before VM runs. If block gas is exhausted
+/// it fails with `ExecutorError::BlockGasLimitReached`.
+pub fn init_gas(
+    acc_balance: u128,
+    msg_balance: u128,
+    is_external: bool,
+    is_special: bool,
+    block_gas: Option<&mut BlockGasReservation>,
+    gas_info: &GasConfigFull
+) -> Result<Gas> {
+    let gas_price = gas_info.get_real_gas_price();
+    if gas_price == 0 {
+        fail!(ExecutorError::TrExecutorError("gas price is less than one nanogram per gas unit".to_string()))
+    }
     let gas_max = if is_special {
         gas_info.special_gas_limit
     } else {
-        std::cmp::min(gas_info.gas_limit, (acc_balance / gas_info.get_real_gas_price() as u128) as u64)
+        let gas_max = std::cmp::min(gas_info.gas_limit, (acc_balance / gas_price as u128) as u64);
+        match block_gas {
+            //transaction cannot use more gas than block has
+            Some(block_gas) if gas_max != 0 => match block_gas.reserve(gas_max) {
+                0 => {
+                    //message is not processed at all, it can be processed in the next block
+                    log::debug!(target: "executor", "block gas limit is reached");
+                    fail!(ExecutorError::BlockGasLimitReached)
+                }
+                reserved => reserved
+            },
+            _ => gas_max
+        }
     };
     let (gas_limit, gas_credit) =
         if is_external {
             (0, std::cmp::min(gas_info.gas_credit, gas_max))
         } else {
-            let gasl = std::cmp::min(gas_max, (msg_balance / gas_info.get_real_gas_price() as u128) as u64);
+            let gasl = std::cmp::min(gas_max, (msg_balance / gas_price as u128) as u64);
             (gasl, 0)
         };
     log::debug!(
         target: "executor", 
         "gas before: gm: {}, gl: {}, gc: {}, price: {}", 
-        gas_max, gas_limit, gas_credit, gas_info.get_real_gas_price()
+        gas_max, gas_limit, gas_credit, gas_price
     );
-    Gas::new(gas_limit as i64, gas_credit as i64, gas_max as i64, gas_info.get_real_gas_price() as i64)
+    Ok(Gas::new(gas_limit as i64, gas_credit as i64, gas_max as i64, gas_price as i64))
+}
+
+/// Mode of TVM run by executor, reference validators always use the default mode 0
+const VM_MODE_DEFAULT: i8 = 0;
+
+/// Parameters of computing phase which do not depend on message and account state
+pub struct ComputePhaseParams<'a> {
+    pub gas_config: &'a GasConfigFull,
+    pub is_special: bool,
+    /// Gas reserved in block for transaction, it must be committed by executor
+    /// after transaction is executed
+    pub block_gas: Option<BlockGasReservation<'a>>,
+    /// Trace VM execution
+    pub debug: bool,
+}
+
+impl<'a> ComputePhaseParams<'a> {
+    /// Commit gas reserved in block with gas used by transaction, unused gas is returned to block
+    pub fn commit_block_gas(self, gas_used: u64) {
+        if let Some(block_gas) = self.block_gas {
+            block_gas.commit(gas_used)
+        }
+    }
 }
 
 /// Implementation of transaction's computing phase.
 /// Evaluates new accout state and invokes TVM if account has contract code.
+/// Hashes of initial and final VM states are zero as in transactions of reference validators.
 pub fn compute_phase(
     msg: Option<&Message>,
     acc: &mut Account, 
+    msg_balance: &mut CurrencyCollection,
     smc_info: &SmartContractInfo, 
-    stack_builder: &dyn TransactionExecutor,
-    config: &GasConfigFull,
-    is_special: bool,
-    debug: bool,                                                                  
+    stack_builder: &dyn StackBuilder,
+    params: &mut ComputePhaseParams,
 ) -> Result<(TrComputePhase, Option<Cell>)> {
-    let mut msg_balance = 0;
+    let config = params.gas_config;
+    let is_special = params.is_special;
     let mut is_external = false;
     let (mut new_acc, mut phase) = match msg {
         Some(ref msg) => {
-            msg_balance = msg.get_value().map(|value| value.grams.value())
-                .cloned().unwrap_or_default().to_u128()
-                .ok_or(ExecutorError::TrExecutorError("Failed to convert msg balance to u128".to_string()))?;
             is_external = msg.is_inbound_external();
-            compute_new_state(acc.clone(), msg)
+            compute_new_state(acc.clone(), msg, smc_info.unix_time())?
         }
         None => (acc.clone(), TrComputePhase::Vm(TrComputePhaseVm::default()))
     };
+    let address = new_acc.get_addr().cloned();
+    let phase_error = |cause: failure::Error| ExecutorError::phase_failed(TrPhase::Compute, address.as_ref(), cause);
 
-    if let TrComputePhase::Skipped(_) = phase {
+    if let TrComputePhase::Skipped(ref skipped) = phase {
+        if is_external {
+            //nobody pays for external message which cannot be processed, so it is rejected
+            let reason = match (&skipped.reason, new_acc.state()) {
+                (ComputeSkipReason::BadState, Some(AccountState::AccountFrozen(_))) => ExtMsgRejectReason::Frozen,
+                (ComputeSkipReason::NoGas, _) => ExtMsgRejectReason::NotAccepted,
+                _ => ExtMsgRejectReason::NoState
+            };
+            fail!(ExecutorError::ext_message_rejected(reason))
+        }
+        //account can be created by the message even if computing phase is skipped
+        *acc = new_acc;
         return Ok((phase, None));
     }
 
     let acc_balance = new_acc.get_balance().cloned().unwrap_or_default()
         .grams.value().to_u128()
-        .ok_or(ExecutorError::TrExecutorError(
-            "Failed to convert account balance to u128".to_string()))?;
+        .ok_or_else(|| ExecutorError::phase_failed(
+            TrPhase::Compute, address.as_ref(), "failed to convert account balance to u128"))?;
+    let msg_balance_grams = msg_balance.grams.value().to_u128()
+        .ok_or_else(|| ExecutorError::phase_failed(
+            TrPhase::Compute, address.as_ref(), "failed to convert msg balance to u128"))?;
     log::debug!(target: "executor", "acc balance: {}", acc_balance);
-    log::debug!(target: "executor", "msg balance: {}", msg_balance);
+    log::debug!(target: "executor", "msg balance: {}", msg_balance_grams);
     //code must present but can be empty (i.g. for uninitialized account)
     let code = new_acc.get_code().unwrap_or_default();
 
-    let gas = init_gas(acc_balance, msg_balance, is_external, is_special, config);
-    let vm_phase = phase.get_vmphase_mut().unwrap();
+    let gas = match init_gas(acc_balance, msg_balance_grams, is_external, is_special, params.block_gas.as_mut(), config) {
+        Ok(gas) => gas,
+        Err(err) => match err.downcast::<ExecutorError>() {
+            Ok(ExecutorError::BlockGasLimitReached) => fail!(ExecutorError::BlockGasLimitReached),
+            Ok(err) => fail!(ExecutorError::phase_failed(TrPhase::Compute, address.as_ref(), err)),
+            Err(err) => fail!(phase_error(err)),
+        }
+    };
+    if gas.get_gas_limit() == 0 && gas.get_gas_credit() == 0 {
+        log::debug!(target: "executor", "skip computing phase no gas");
+        if is_external {
+            fail!(ExecutorError::ext_message_rejected(ExtMsgRejectReason::NotAccepted))
+        }
+        *acc = new_acc;
+        return Ok((TrComputePhase::Skipped(
+            TrComputePhaseSkipped { reason: ComputeSkipReason::NoGas }
+        ), None));
+    }
+    let vm_phase = phase.get_vmphase_mut().ok_or_else(|| ExecutorError::phase_failed(
+        TrPhase::Compute, address.as_ref(), "compute phase is not VM phase"))?;
     vm_phase.gas_credit = match gas.get_gas_credit() as u32 {
         0 => None,
         value => Some(value.into())
     };
     vm_phase.gas_limit = (gas.get_gas_limit() as u64).into();
 
+    let data = new_acc.get_data().unwrap_or(Cell::default());
+    //reference validators do not calculate VM state hashes and always write zero ones,
+    //there is no defined representation of VM state to hash, so zero is written here too
+    //to keep transactions byte-identical with theirs
+    vm_phase.vm_init_state_hash = UInt256::default();
+
     let mut vm = VMSetup::new(code.into())
-        .set_contract_info(&smc_info)
-        .set_stack(stack_builder.build_stack(msg, &new_acc))
-        .set_data(new_acc.get_data().unwrap_or(Cell::default()))
+        .set_contract_info(&smc_info).map_err(phase_error)?
+        .set_stack(stack_builder.build_stack(msg, &new_acc).map_err(phase_error)?)
+        .set_data(data).map_err(phase_error)?
         .set_gas(gas)
-        .set_debug(debug)
+        .set_debug(params.debug)
         .create();
-    
-    //TODO: set vm_init_state_hash
 
+    let mut exception = None;
     match vm.execute() {
         Err(e) => {
             log::debug!(target: "executor", "VM terminated with exception: {}", e);
+            exception = Some(e.to_string());
             vm_phase.exit_code = if let Some(TvmError::TvmExceptionFull(e)) = e.downcast_ref() {
                 e.number as i32
             } else if let Some(TvmError::TvmException(e)) = e.downcast_ref() {
@@ -224,33 +451,39 @@ pub fn compute_phase(
     vm_phase.gas_used = VarUInteger7(used.into());
     if credit != 0 {
         if is_external {
-            fail!(
-                ExecutorError::TrExecutorError( 
-                    "Contract did not accepted on external message processing".to_string()
-                )
-            )
+            fail!(ExecutorError::ExtMessageRejected {
+                reason: ExtMsgRejectReason::NotAccepted,
+                exit_code: Some(vm_phase.exit_code),
+                gas_used: Some(used),
+                exception,
+            })
         }
         vm_phase.gas_fees = Grams::zero();
     } else { // credit == 0 means contract accepted
         let gas_fees = if is_special { 0 } else { config.calc_gas_fee(used) };
         vm_phase.gas_fees = Grams(gas_fees.into());
     };
-    //TODO: get vm steps
+    vm_phase.vm_steps = vm.steps();
 
     log::debug!(
         target: "executor", 
-        "gas after: gl: {}, gc: {}, gu: {}, fees:{}", 
-        gas.get_gas_limit() as u64, credit, used, vm_phase.gas_fees.0
+        "gas after: gl: {}, gc: {}, gu: {}, fees:{}, steps: {}", 
+        gas.get_gas_limit() as u64, credit, used, vm_phase.gas_fees.0, vm_phase.vm_steps
     );
    
-    //set mode
-    vm_phase.mode = 0;
-
-    //TODO: set vm_steps
-    //TODO: vm_final_state_hash
+    vm_phase.mode = VM_MODE_DEFAULT;
     let gas_fees = vm_phase.gas_fees.clone();
-    //exact gass from account balance, balance cannot be less than gas_fees.
-    new_acc.sub_funds(&CurrencyCollection::from_grams(gas_fees)).unwrap();
+    //gas is paid from inbound message value first, the rest of fees is taken from account
+    if !msg_balance.grams.sub(&gas_fees).map_err(phase_error)? {
+        msg_balance.grams = Grams::zero();
+    }
+    //exact gass from account balance, if balance is less than gas_fees all its grams are taken
+    if !new_acc.sub_funds(&CurrencyCollection::from_grams(gas_fees)).map_err(phase_error)? {
+        log::debug!(target: "executor", "account cannot pay all gas fees, its whole balance is taken");
+        let grams = new_acc.get_balance().map(|balance| balance.grams.clone()).unwrap_or_default();
+        new_acc.sub_funds(&CurrencyCollection::from_grams(grams.clone())).map_err(phase_error)?;
+        vm_phase.gas_fees = grams;
+    }
     
     match vm.get_committed_state().get_root() {
         StackItem::Cell(cell) => { new_acc.set_data(cell); },
@@ -259,6 +492,7 @@ pub fn compute_phase(
             vm_phase.success = false;
         },
     }
+    vm_phase.vm_final_state_hash = UInt256::default();
     *acc = new_acc;
 
     let out_actions = match vm.get_committed_state().get_actions() {
@@ -273,68 +507,84 @@ pub fn compute_phase(
     Ok((phase, out_actions))
 }
 
-fn create_account_state(in_msg: &Message, bounce: bool) -> (Account, TrComputePhase) {
+fn create_account_state(in_msg: &Message, bounce: bool) -> Result<(Account, TrComputePhase)> {
     log::debug!(target: "executor", "create_account_state");
     let skipped_phase_no_state = TrComputePhase::Skipped(
         TrComputePhaseSkipped { reason: ComputeSkipReason::NoState }
     );
+    if !check_state_init_hash(in_msg) {
+        //constructor message cannot deploy code at address not derived from it
+        log::debug!(target: "executor", "StateInit hash is not equal to account address");
+        let skipped_phase_bad_state = TrComputePhase::Skipped(
+            TrComputePhaseSkipped { reason: ComputeSkipReason::BadState }
+        );
+        return Ok(match (bounce, in_msg.dst(), in_msg.get_value()) {
+            //value-bearing message with no bounce: keep value on uninitialized account
+            (false, Some(address), Some(balance)) => 
+                (Account::with_address_and_ballance(&address, balance), skipped_phase_bad_state),
+            _ => (Account::default(), skipped_phase_bad_state)
+        })
+    }
     //try to create account with constructor message
-    let new_acc = Account::with_message(in_msg);
-    if new_acc.is_err() {
-        //message has no code and data,
-        //check bounce flag
-        if bounce {
-            //let skip computing phase, because account not exist and bounce flag is setted. 
-            //Account will not be created, return AccountNone
-            (Account::default(), skipped_phase_no_state)
-        } else if in_msg.get_value().is_some() {
-            //value-bearing message with no bounce: create uninitialized account
-            log::debug!(target: "executor", "new uninitialized acc is created");
-            let address = in_msg.dst().unwrap();
-            let balance = in_msg.get_value();
-            (
-                Account::with_address_and_ballance(&address, balance.unwrap_or(&CurrencyCollection::default())),
-                TrComputePhase::Vm(TrComputePhaseVm::default())
-            )
-        } else {
-            //external message: skip computing phase and account will not be created
-            //return undefined account
-            (Account::default(), skipped_phase_no_state)
+    match Account::with_message(in_msg) {
+        Err(_) => {
+            //message has no code and data,
+            //check bounce flag
+            if bounce {
+                //let skip computing phase, because account not exist and bounce flag is setted. 
+                //Account will not be created, return AccountNone
+                Ok((Account::default(), skipped_phase_no_state))
+            } else if let Some(balance) = in_msg.get_value() {
+                //value-bearing message with no bounce: create uninitialized account
+                log::debug!(target: "executor", "new uninitialized acc is created");
+                let address = in_msg.dst().ok_or_else(|| ExecutorError::InvalidMessage(
+                    "inbound message has no destination address".to_string()))?;
+                Ok((
+                    Account::with_address_and_ballance(&address, balance),
+                    TrComputePhase::Vm(TrComputePhaseVm::default())
+                ))
+            } else {
+                //external message: skip computing phase and account will not be created
+                //return undefined account
+                Ok((Account::default(), skipped_phase_no_state))
+            }
         }
-    } else {
-        //account created from constructor message
-        //but check that inbound message bear some value,
-        //otherwise it will be frozen
-        if in_msg.get_value().is_some() {
-
-            log::debug!(target: "executor", "new acc is created");
-
-            //it's ok - active account will be created.
-            //set apropriate flags in phase.
-            let mut phase = TrComputePhase::Vm(TrComputePhaseVm::default());
-            phase.activated(true);
-            //return activated account
-            (new_acc.unwrap(), phase)
-        } else {
-            log::debug!(target: "executor", "new acc is created and frozen");
-            (
-                new_acc.and_then(|mut acc| { acc.freeze_account(); Ok(acc) }).unwrap(),
-                skipped_phase_no_state,
-            )
+        Ok(mut new_acc) => {
+            //account created from constructor message
+            //but check that inbound message bear some value,
+            //otherwise it will be frozen
+            if in_msg.get_value().is_some() {
+
+                log::debug!(target: "executor", "new acc is created");
+
+                //it's ok - active account will be created.
+                //set apropriate flags in phase.
+                let mut phase = TrComputePhase::Vm(TrComputePhaseVm::default());
+                phase.activated(true);
+                //return activated account
+                Ok((new_acc, phase))
+            } else {
+                log::debug!(target: "executor", "new acc is created and frozen");
+                new_acc.freeze_account();
+                Ok((new_acc, skipped_phase_no_state))
+            }
         }
     }
 }
 
-fn compute_account_state(mut acc: Account, in_msg: &Message, bounce: bool) -> (Account, TrComputePhase) {
+fn compute_account_state(mut acc: Account, in_msg: &Message, bounce: bool) -> Result<(Account, TrComputePhase)> {
     log::debug!(target: "executor", "compute_account_state");    
     let mut phase = TrComputePhase::Vm(TrComputePhaseVm::default());
     let skipped_phase_no_state = TrComputePhase::Skipped(
         TrComputePhaseSkipped { reason: ComputeSkipReason::NoState }
     );
-    let state = acc.state().expect("account must exist");
+    let state = match acc.state() {
+        Some(state) => state,
+        None => fail!(ExecutorError::invalid_account(acc.get_addr(), "account has no state"))
+    };
     
     //Account exists, but can be in different states.
-    match state {
+    let result = match state {
         AccountState::AccountActive(_) => {
             //account is active, just return it
             log::debug!(target: "executor", "account state: AccountActive");
@@ -343,6 +593,13 @@ fn compute_account_state(mut acc: Account, in_msg: &Message, bounce: bool) -> (A
         AccountState::AccountUninit => {
             log::debug!(target: "executor", "AccountUninit");
             if let Some(state_init) = in_msg.state_init() {
+                if !check_state_init_hash(in_msg) {
+                    //skip computing phase, because StateInit does not belong to account
+                    log::debug!(target: "executor", "StateInit hash is not equal to account address");
+                    return Ok((acc, TrComputePhase::Skipped(
+                        TrComputePhaseSkipped { reason: ComputeSkipReason::BadState }
+                    )))
+                }
                 // if msg is a constructor message then
                 // borrow code and data from it and switch account state to 'active'.
                 acc.activate(state_init.clone());
@@ -373,16 +630,21 @@ fn compute_account_state(mut acc: Account, in_msg: &Message, bounce: bool) -> (A
                 }
             }
         }
-        AccountState::AccountFrozen(_) => {
+        AccountState::AccountFrozen(frozen_hash) => {
             log::debug!(target: "executor", "AccountFrozen");
+            let frozen_hash = frozen_hash.clone();
             //account balance was credited and if it positive after that
             //and inbound message bear code and data then make some check and unfreeze account
-            if !acc.get_balance().unwrap().grams.is_zero() {
+            if acc.get_balance().map(|balance| !balance.grams.is_zero()).unwrap_or(false) {
                 if let Some(state_init) = in_msg.state_init() {
-                    log::debug!(target: "executor", "external message for frozen: activated");
-                    acc.activate(state_init.clone());
-                    phase.activated(true);
-                    return (acc, phase)
+                    //only the state which account had before freezing can be restored
+                    if state_init.hash().map(|hash| hash == frozen_hash).unwrap_or(false) {
+                        log::debug!(target: "executor", "external message for frozen: activated");
+                        acc.activate(state_init.clone());
+                        phase.activated(true);
+                        return Ok((acc, phase))
+                    }
+                    log::debug!(target: "executor", "StateInit hash is not equal to frozen state hash");
                 }
             }
             //skip computing phase, because account is frozen (bad state)
@@ -392,6 +654,19 @@ fn compute_account_state(mut acc: Account, in_msg: &Message, bounce: bool) -> (A
             );
             (acc, phase)
         }
+    };
+    Ok(result)
+}
+
+/// Checks that representation hash of StateInit from message is equal to destination account id.
+/// Message without StateInit passes the check.
+fn check_state_init_hash(in_msg: &Message) -> bool {
+    match (in_msg.state_init(), in_msg.dst()) {
+        (Some(state_init), Some(dst)) => state_init.hash()
+            .map(|hash| AccountId::from(hash) == dst.get_address())
+            .unwrap_or(false),
+        (Some(_), None) => false,
+        (None, _) => true
     }
 }
 
@@ -399,14 +674,21 @@ fn compute_account_state(mut acc: Account, in_msg: &Message, bounce: bool) -> (A
 /// If account does not exist - it can be created with uninitialized state.
 /// If account is uninitialized - it can be created with active state.
 /// If account exists - it can be frozen.
+/// Storage of created account is paid since transaction time `now`.
 /// Returns computed initial phase.
-fn compute_new_state(acc: Account, in_msg: &Message) -> (Account, TrComputePhase) {
+fn compute_new_state(acc: Account, in_msg: &Message, now: u32) -> Result<(Account, TrComputePhase)> {
     let mut bounce = false;
     if let CommonMsgInfo::IntMsgInfo(ref header) = in_msg.header() {
         bounce = header.bounce;
     }
     match acc {
-        Account::AccountNone => create_account_state(in_msg, bounce),
+        Account::AccountNone => {
+            let (mut new_acc, phase) = create_account_state(in_msg, bounce)?;
+            if new_acc != Account::AccountNone {
+                new_acc.set_last_paid(now);
+            }
+            Ok((new_acc, phase))
+        }
         _ => compute_account_state(acc, in_msg, bounce),
     }
 }
@@ -419,14 +701,20 @@ fn compute_new_state(acc: Account, in_msg: &Message) -> (Account, TrComputePhase
 /// substracted from account balance. If account has enough funds this 
 /// will be succeded, otherwise action phase is failed, transaction will be
 /// marked as aborted, account changes will be rollbacked.
+/// Remaining value of inbound message is sent by the first message with
+/// `SENDMSG_REMAINING_MSG_BALANCE` flag, then `msg_remaining_balance` becomes zero.
 pub fn action_phase(
     tr: &mut Transaction,
     acc: &mut Account,
     actions_cell: Option<Cell>,
+    msg_remaining_balance: &mut CurrencyCollection,
     config: &BlockchainConfig,
-    lt: Arc<AtomicU64>,
+    lt: &mut u64,
     is_special: bool,
-) -> Option<TrActionPhase> {
+) -> Result<Option<TrActionPhase>> {
+    let myself = acc.get_addr().cloned()
+        .ok_or_else(|| ExecutorError::invalid_account(None, "account has no address"))?;
+    let phase_error = |cause: failure::Error| ExecutorError::phase_failed(TrPhase::Action, Some(&myself), cause);
     let mut phase = TrActionPhase::default();
     let mut total_spend_value = CurrencyCollection::default();
     let mut total_reserved_value = CurrencyCollection::default();
@@ -434,12 +722,15 @@ pub fn action_phase(
     let mut msg_action_count = 0i16;
     let mut special_action_count = 0i16;
     let skipped_action_count = 0i16;
+    let mut account_deleted = false;
     let mut actions = OutActions::default();
-    let mut remaining_balance = acc.get_balance().unwrap().clone();
+    let original_balance = acc.get_balance().cloned()
+        .ok_or_else(|| ExecutorError::invalid_account(Some(&myself), "account has no balance"))?;
+    let mut remaining_balance = original_balance.clone();
     phase.tot_actions = 0;
     phase.spec_actions = 0;
     phase.msgs_created = 0;
-    phase.result_code = 0;
+    phase.result_code = ActionResultCode::Success.into();
     phase.result_arg = None;
     phase.valid = true;
     phase.success = true;
@@ -453,18 +744,18 @@ pub fn action_phase(
         );
         phase.success = false;
         phase.valid = false;
-        phase.result_code = RESULT_CODE_ACTIONLIST_INVALID;
-        return Some(phase);
+        phase.result_code = ActionResultCode::ActionListInvalid.into();
+        return Ok(Some(phase));
     }
 
     if actions.len() > MAX_ACTIONS {
         log::debug!(target: "executor", "too many actions: {}", actions.len());
         phase.success = false;
         phase.valid = false;
-        phase.result_code = RESULT_CODE_TOO_MANY_ACTIONS;
-        return Some(phase);
+        phase.result_code = ActionResultCode::TooManyActions.into();
+        return Ok(Some(phase));
     }
-    phase.action_list_hash = actions.hash().unwrap();
+    phase.action_list_hash = actions.hash().map_err(phase_error)?;
     phase.tot_actions = actions.len() as i16;
 
     for (i, action) in actions.iter_mut().enumerate() {
@@ -474,21 +765,24 @@ pub fn action_phase(
             OutAction::SendMsg{ mode, mut out_msg } => {
                 let result = outmsg_action_handler(
                     &mut phase, 
-                    acc.get_addr().unwrap().clone(), 
+                    myself.clone(), 
                     mode, 
                     Arc::make_mut(&mut out_msg),
-                    lt.fetch_add(1, Ordering::SeqCst),
+                    *lt,
                     tr.now(),
                     &mut remaining_balance,
+                    msg_remaining_balance,
                     config,
-                    is_special
+                    is_special,
+                    &mut account_deleted,
                 );
                 match result {
                     Ok(msg_balance) => {
                         msg_action_count += 1;
+                        *lt += 1;
                         out_msgs.push(out_msg);
                         total_spend_value.add(&msg_balance).ok()
-                            .map_or(RESULT_CODE_INVALID_BALANCE, |_| 0)
+                            .map_or(ActionResultCode::InvalidBalance, |_| ActionResultCode::Success)
                     },
                     Err(code) => code,
                 }
@@ -498,78 +792,93 @@ pub fn action_phase(
                     Ok(reserved_value) => {
                         special_action_count += 1;
                         total_reserved_value.add(&reserved_value).ok()
-                            .map_or(RESULT_CODE_INVALID_BALANCE, |_| 0)
+                            .map_or(ActionResultCode::InvalidBalance, |_| ActionResultCode::Success)
                     },
                     Err(code) => code,
                 }
             },
             OutAction::SetCode{ new_code: code } => {
                 log::debug!(target: "executor", "OutAction::SetCode {}", code);
-                setcode_action_handler(acc, code).unwrap_or({
-                    special_action_count += 1;
-                    0
-                })
+                match setcode_action_handler(acc, code) {
+                    Some(code) => code,
+                    None => {
+                        special_action_count += 1;
+                        ActionResultCode::Success
+                    }
+                }
             },
-            OutAction::None => {
-                RESULT_CODE_UNKNOWN_ACTION
+            OutAction::ChangeLibrary{ mode, code, hash } => {
+                log::debug!(target: "executor", "OutAction::ChangeLibrary mode {}", mode);
+                match change_library_action_handler(acc, mode, code, hash) {
+                    Some(code) => code,
+                    None => {
+                        special_action_count += 1;
+                        ActionResultCode::Success
+                    }
+                }
             },
             _ => {
-                unimplemented!()
+                ActionResultCode::UnknownAction
             }
         };
-        if err_code != 0 {
+        if !err_code.is_success() {
             log::debug!(target: "executor", "action failed: error_code={}", err_code);
-            phase.result_code = err_code;
+            phase.result_code = err_code.into();
             phase.result_arg = Some(i as i32);
             phase.valid = true;
             phase.success = false;
             phase.msgs_created = msg_action_count;
-            if err_code == RESULT_CODE_NOT_ENOUGH_GRAMS {
+            if err_code.is_no_funds() {
                 phase.no_funds = true;
             }
-            return Some(phase);
+            return Ok(Some(phase));
         }        
     }
 
     //calc new account balance
     let mut new_balance = remaining_balance.clone();
-    new_balance.add(&total_reserved_value)
-        .map_err(
-            |e| { 
-                log::debug!(target: "executor", "failed to add account balance with reserved value"); 
-                e 
-            }
-        )
-        .ok()?;
+    if let Err(err) = new_balance.add(&total_reserved_value) {
+        log::debug!(target: "executor", "failed to add account balance with reserved value: {}", err);
+        phase.success = false;
+        phase.result_code = ActionResultCode::InvalidBalance.into();
+        return Ok(Some(phase));
+    }
     //calc difference of new balance from old balance
-    let mut balance_diff = acc.get_balance().unwrap().clone();
+    let mut balance_diff = original_balance;
     //must be succeded, because check is already done
-    assert_eq!(balance_diff.sub(&new_balance).ok()?, true);
+    if !balance_diff.sub(&new_balance).map_err(phase_error)? {
+        fail!(ExecutorError::phase_failed(TrPhase::Action, Some(&myself), "new balance exceeds original balance"))
+    }
 
     //TODO: substract difference from account balance
     if !acc.sub_funds(&balance_diff).unwrap_or(false) {
         log::debug!(target: "executor", "account balance doesn't have enought grams");
         phase.success = false;
         phase.no_funds = true;
-        phase.result_code = RESULT_CODE_INVALID_BALANCE;
+        phase.result_code = ActionResultCode::InvalidBalance.into();
     } else {
         //TODO: some kind of check to freeze account.
     }
 
     for msg in out_msgs {
-        tr.add_out_message(&msg).ok()?;
+        tr.add_out_message(&msg).map_err(phase_error)?;
     }
     tr.total_fees_mut()
         .add(&CurrencyCollection::from_grams(
             phase.total_action_fees.clone().unwrap_or(Grams::zero()
-    ))).unwrap();
+    ))).map_err(phase_error)?;
 
-    //for now we support only this change status
-    phase.status_change = AccStatusChange::Unchanged;
+    //account is deleted only if it has no funds after all actions
+    phase.status_change = if account_deleted && is_empty_balance(&new_balance).map_err(phase_error)? {
+        log::debug!(target: "executor", "account will be deleted");
+        AccStatusChange::Deleted
+    } else {
+        AccStatusChange::Unchanged
+    };
     phase.spec_actions = special_action_count;
     phase.msgs_created = msg_action_count;
     phase.skipped_actions = skipped_action_count;
-    Some(phase)
+    Ok(Some(phase))
 }
 
 fn outmsg_action_handler(
@@ -580,15 +889,17 @@ fn outmsg_action_handler(
     lt: u64,
     ut: u32,
     remaining: &mut CurrencyCollection,
+    msg_balance: &mut CurrencyCollection,
     config: &BlockchainConfig,
     is_special: bool,
-) -> std::result::Result<CurrencyCollection, i32> {
+    account_deleted: &mut bool,
+) -> std::result::Result<CurrencyCollection, ActionResultCode> {
     let invalid_flags = SENDMSG_REMAINING_MSG_BALANCE | SENDMSG_ALL_BALANCE;
     if  (mode & !SENDMSG_VALID_FLAGS) != 0 ||
         (mode & invalid_flags) == invalid_flags
     {
         log::error!(target: "executor", "outmsg mode has unsupported flags");
-        return Err(RESULT_CODE_UNSUPPORTED);
+        return Err(ActionResultCode::Unsupported);
     }
     let skip = (mode & SENDMSG_IGNORE_ERROR) != 0;
     let value = msg.get_value().map(|v| v.clone()).unwrap_or(CurrencyCollection::default());
@@ -599,33 +910,40 @@ fn outmsg_action_handler(
 
     match msg.header_mut() {
         CommonMsgInfo::IntMsgInfo(ref mut int_header) => {
+            int_header.src = match check_replace_src_addr(&int_header.src, &myself) {
+                Some(src) => MsgAddressIntOrNone::Some(src),
+                None => return if skip { Err(ActionResultCode::Success) } else { Err(ActionResultCode::IncorrectSrcAddress) }
+            };
+            int_header.dst = match check_rewrite_dest_addr(&int_header.dst, config, &myself) {
+                Ok(dst) => dst,
+                Err(code) => return if skip { Err(ActionResultCode::Success) } else { Err(code) }
+            };
             int_header.created_at = ut.into();
             int_header.created_lt = lt;
-            int_header.src = MsgAddressIntOrNone::Some(myself);
             fwd_fee = int_header.fwd_fee.clone();
             ihr_fee = int_header.ihr_fee.clone();
             is_internal_msg = true;
             ihr_disabled = int_header.ihr_disabled;
         },
         CommonMsgInfo::ExtOutMsgInfo(ref mut ext_header) => {
+            ext_header.src = match check_replace_src_addr(&ext_header.src, &myself) {
+                Some(src) => MsgAddressIntOrNone::Some(src),
+                None => return if skip { Err(ActionResultCode::Success) } else { Err(ActionResultCode::IncorrectSrcAddress) }
+            };
             ext_header.created_at = ut.into();
             ext_header.created_lt = lt;
-            ext_header.src = MsgAddressIntOrNone::Some(myself);
             ihr_disabled = true;
         },
-        CommonMsgInfo::ExtInMsgInfo(_) => return Err(-1),
+        CommonMsgInfo::ExtInMsgInfo(_) => return Err(ActionResultCode::Unsupported),
     };
 
-    // TODO: check and rewrite src and dst adresses (see check_replace_src_addr and 
-    // check_rewrite_dest_addr functions)
-
-    let config = config.get_fwd_prices(msg);
+    let fwd_prices = config.get_fwd_prices(msg);
 
     let compute_fwd_fee = if is_special {
         Grams::zero()
     } else {
-        config.calc_fwd_fee(msg)
-            .map_err(|_| RESULT_CODE_ACTIONLIST_INVALID)?
+        fwd_prices.calc_fwd_fee(msg)
+            .map_err(|_| ActionResultCode::ActionListInvalid)?
             .1
             .into()
     };
@@ -635,44 +953,48 @@ fn outmsg_action_handler(
     }
 
     if !ihr_disabled {
-        let compute_ihr_fee = config.calc_ihr_fee(compute_fwd_fee.value().to_u128().unwrap_or(0)).into();
+        let compute_ihr_fee = fwd_prices.calc_ihr_fee(compute_fwd_fee.value().to_u128().unwrap_or(0)).into();
         if ihr_fee < compute_ihr_fee {
             ihr_fee = compute_ihr_fee;
         }
     }
 
     let fwd_mine_fee = if is_internal_msg {
-        config.calc_mine_fee(fwd_fee.value().to_u128().unwrap_or(0)).into()
+        fwd_prices.calc_mine_fee(fwd_fee.value().to_u128().unwrap_or(0)).into()
     } else {
         fwd_fee.clone()
     };
     let fwd_remain_fee = fwd_fee.0.clone() - fwd_mine_fee.0.clone();
 
-    let mut result_grams = value.grams.clone();
+    let mut result_value = value.clone();
     let mut new_msg_value = value.clone();
     if is_internal_msg {
         if (mode & SENDMSG_ALL_BALANCE) != 0 {
             //send all remaining account balance
-            result_grams = remaining.grams.clone();
+            result_value = remaining.clone();
             new_msg_value = remaining.clone();
 
             mode &= !SENDMSG_PAY_FEE_SEPARATELY;
         }
-        // TODO: process SENDMSG_REMAINING_MSG_BALANCE flag
-        
+        if (mode & SENDMSG_REMAINING_MSG_BALANCE) != 0 {
+            //send remaining value of inbound message in addition to the value set in message
+            result_value.add(msg_balance).or(Err(ActionResultCode::InvalidBalance))?;
+            new_msg_value.add(msg_balance).or(Err(ActionResultCode::InvalidBalance))?;
+        }
+
         if (mode & SENDMSG_PAY_FEE_SEPARATELY) != 0 {
             //we must pay the fees, sum them with msg value
-            result_grams.0 += &ihr_fee.0;
-            result_grams.0 += &fwd_fee.0;
+            result_value.grams.0 += &ihr_fee.0;
+            result_value.grams.0 += &fwd_fee.0;
         } else if new_msg_value.grams.0 < (&fwd_fee.0 + &ihr_fee.0) {
             //msg value is too small, reciever cannot pay the fees 
-            return if skip { Err(0) } else { 
+            return if skip { Err(ActionResultCode::Success) } else { 
                 log::error!(
                     target: "executor", 
                     "msg balance is too small, cannot pay fwd+ihr fees: need = {}, have = {}", 
                     &fwd_fee.0 + &ihr_fee.0, new_msg_value.grams.0
                 );
-                Err(RESULT_CODE_NOT_ENOUGH_GRAMS) 
+                Err(ActionResultCode::NotEnoughGrams) 
             };
         } else {
             //reciever will pay the fees
@@ -680,13 +1002,23 @@ fn outmsg_action_handler(
             new_msg_value.grams.0 -= &fwd_fee.0;
         }
 
-        if remaining.grams.0 < result_grams.0 {
-            return if skip { Err(0) } else {
+        if remaining.grams.0 < result_value.grams.0 {
+            return if skip { Err(ActionResultCode::Success) } else {
                 log::error!(
                     target: "executor", 
                     "account balance is too small, cannot send so many grams"
                 ); 
-                Err(RESULT_CODE_NOT_ENOUGH_GRAMS) 
+                Err(ActionResultCode::NotEnoughGrams) 
+            };
+        }
+
+        if !check_extra_currencies(remaining, &result_value) {
+            return if skip { Err(ActionResultCode::Success) } else {
+                log::error!(
+                    target: "executor", 
+                    "account balance is too small, cannot send so many extra currencies"
+                ); 
+                Err(ActionResultCode::NotEnoughExtra) 
             };
         }
 
@@ -699,10 +1031,33 @@ fn outmsg_action_handler(
 
     } else {
         // TODO: check if account can pay fee
-        result_grams = fwd_fee.clone();
+        result_value = CurrencyCollection::from_grams(fwd_fee.clone());
     }
 
-    // TODO: process SENDMSG_DELETE_IF_EMPTY flag
+    let msg_cell: Cell = msg
+        .write_to_new_cell()
+        .map_err(|_| ActionResultCode::ActionListInvalid)?
+        .into();
+    let limits = config.get_msg_size_limits();
+    let msg_size = StorageUsedShort::calculate_for_cell(&msg_cell);
+    if  msg_size.cells.0 > limits.max_msg_cells ||
+        msg_size.bits.0 > limits.max_msg_bits ||
+        msg_cell.repr_depth() > limits.max_msg_depth
+    {
+        return if skip { Err(ActionResultCode::Success) } else {
+            log::error!(
+                target: "executor", 
+                "outbound message is too large: cells = {}, bits = {}, depth = {}", 
+                msg_size.cells.0, msg_size.bits.0, msg_cell.repr_depth()
+            );
+            Err(ActionResultCode::MsgTooLarge)
+        };
+    }
+
+    if (mode & SENDMSG_ALL_BALANCE) != 0 && (mode & SENDMSG_DELETE_IF_EMPTY) != 0 {
+        //account must be deleted if its balance becomes empty after sending all balance
+        *account_deleted = true;
+    }
 
     // total fwd fees is sum of messages full fwd and ihr fees
     let total_fwd_fees = phase.total_fwd_fees.take().unwrap_or(Grams::default());
@@ -712,16 +1067,116 @@ fn outmsg_action_handler(
     let total_action_fees = phase.total_action_fees.take().unwrap_or(Grams::default());
     phase.total_action_fees = Some(Grams(total_action_fees.0 + fwd_mine_fee.0));
 
-    phase.tot_msg_size.append(
-        &msg
-            .write_to_new_cell()
-            .map_err(|_| RESULT_CODE_ACTIONLIST_INVALID)?
-            .into());
+    phase.tot_msg_size.append(&msg_cell);
+
+    remaining.sub(&result_value).or(Err(ActionResultCode::InvalidBalance))?;
+    if is_internal_msg && (mode & SENDMSG_REMAINING_MSG_BALANCE) != 0 {
+        //remaining value of inbound message is sent, it cannot be sent again
+        *msg_balance = CurrencyCollection::default();
+    }
+    Ok(result_value)
+}
+
+/// Checks source address of outbound message.
+/// Empty address is replaced by account address, other address must be equal to account address.
+fn check_replace_src_addr(src: &MsgAddressIntOrNone, myself: &MsgAddressInt) -> Option<MsgAddressInt> {
+    match src {
+        MsgAddressIntOrNone::None => Some(myself.clone()),
+        MsgAddressIntOrNone::Some(addr) => {
+            if addr.get_workchain_id() == myself.get_workchain_id() && 
+                addr.get_address() == myself.get_address() {
+                Some(myself.clone())
+            } else {
+                log::debug!(target: "executor", "outbound message src address {} is not equal to account address", addr);
+                None
+            }
+        }
+    }
+}
+
+/// Maximal depth of anycast address prefix (`depth:(#<= 30)` in `Anycast` TL-B)
+const MAX_ANYCAST_DEPTH: usize = 30;
+
+/// Checks destination address of outbound internal message and rewrites it if needed.
+/// Destination workchain must be accessible. Anycast address prefix is replaced by
+/// the prefix of account address and `addr_var` address is packed to `addr_std` if possible.
+fn check_rewrite_dest_addr(
+    dst: &MsgAddressInt,
+    config: &BlockchainConfig,
+    myself: &MsgAddressInt,
+) -> std::result::Result<MsgAddressInt, ActionResultCode> {
+    let (anycast, addr_len, workchain_id, mut address, mut repack) = match dst {
+        MsgAddressInt::AddrStd(addr) => 
+            (addr.anycast.clone(), 256, addr.workchain_id as i32, addr.address.clone(), false),
+        MsgAddressInt::AddrVar(addr) => 
+            (addr.anycast.clone(), addr.addr_len.0 as usize, addr.workchain_id, addr.address.clone(), true),
+    };
+
+    if !config.is_workchain_accessible(workchain_id) {
+        log::debug!(target: "executor", "destination workchain {} is not accessible", workchain_id);
+        return Err(ActionResultCode::IncorrectDstAddress)
+    }
+
+    if let Some(anycast) = anycast {
+        let depth = anycast.depth.0 as usize;
+        if workchain_id == MASTERCHAIN_ID || !(1..=MAX_ANYCAST_DEPTH).contains(&depth) || depth > addr_len {
+            log::debug!(target: "executor", "invalid anycast destination address {}", dst);
+            return Err(ActionResultCode::IncorrectDstAddress)
+        }
+        //replace anycast prefix with the prefix of account address
+        let mut prefix = myself.get_address();
+        let mut builder = BuilderData::new();
+        prefix.get_next_bits(depth)
+            .and_then(|bits| builder.append_raw(&bits, depth).map(|_| ()))
+            .and_then(|_| address.get_next_bits(depth))
+            .and_then(|_| builder.append_bytestring(&address).map(|_| ()))
+            .map_err(|_| ActionResultCode::IncorrectDstAddress)?;
+        address = builder.into();
+        repack = true;
+    }
 
-    // TODO: subtract all currencies from account balance
-    let value = CurrencyCollection::from_grams(result_grams);
-    remaining.sub(&value).or(Err(RESULT_CODE_INVALID_BALANCE))?;
-    Ok(value)
+    if !repack {
+        return Ok(dst.clone())
+    }
+    let result = if addr_len == 256 && workchain_id >= -128 && workchain_id < 128 {
+        MsgAddressInt::with_standart(None, workchain_id as i8, address)
+    } else {
+        MsgAddressInt::with_variant(None, workchain_id, address)
+    };
+    result.map_err(|_| ActionResultCode::IncorrectDstAddress)
+}
+
+/// Checks that balance has enough funds of every extra currency presented in value.
+fn check_extra_currencies(balance: &CurrencyCollection, value: &CurrencyCollection) -> bool {
+    value.other.iterate_with_keys(|id: u32, amount: VarUInteger32| -> Result<bool> {
+        Ok(balance.other.get(&id)?.map(|have| have.0 >= amount.0).unwrap_or(false))
+    }).unwrap_or(false)
+}
+
+/// Checks if balance has neither grams nor any extra currency.
+fn is_empty_balance(balance: &CurrencyCollection) -> Result<bool> {
+    if !balance.grams.is_zero() {
+        return Ok(false)
+    }
+    balance.other.iterate_with_keys(|_id: u32, amount: VarUInteger32| -> Result<bool> {
+        Ok(amount.is_zero())
+    })
+}
+
+/// Returns collection with minimal value of every currency from value and balance.
+fn min_currencies(value: &CurrencyCollection, balance: &CurrencyCollection) -> Result<CurrencyCollection> {
+    let grams = if value.grams.0 < balance.grams.0 {
+        value.grams.clone()
+    } else {
+        balance.grams.clone()
+    };
+    let mut result = CurrencyCollection::from_grams(grams);
+    value.other.iterate_with_keys(|id: u32, amount: VarUInteger32| -> Result<bool> {
+        let have = balance.other.get(&id)?.unwrap_or_default();
+        result.other.set(&id, if have.0 < amount.0 { &have } else { &amount })?;
+        Ok(true)
+    })?;
+    Ok(result)
 }
 
 /// Reserves some grams from accout balance. 
@@ -731,64 +1186,128 @@ fn reserve_action_handler(
     mode: u8, 
     val: &CurrencyCollection,
     remaining: &mut CurrencyCollection,
-) -> std::result::Result<CurrencyCollection, i32>  {
+) -> std::result::Result<CurrencyCollection, ActionResultCode>  {
     if (mode & !RESERVE_VALID_MODES) != 0 {
-        return Err(RESULT_CODE_UNSUPPORTED);
+        return Err(ActionResultCode::Unsupported);
     }
 
     let mut reserved = val.clone();
-    //TODO: need to compare extra currencies too.
-    if reserved.grams.0 > remaining.grams.0 {
+    let enough_grams = reserved.grams.0 <= remaining.grams.0;
+    if !enough_grams || !check_extra_currencies(remaining, &reserved) {
         //reserving more than remaining balance has,
         //this is error, but check the flag
         if (mode & RESERVE_IGNORE_ERROR) != 0 {
-            //reserve all remaining balance
-            reserved = remaining.clone();
+            //reserve as much as remaining balance has
+            reserved = min_currencies(&reserved, remaining).or(Err(ActionResultCode::InvalidBalance))?;
+        } else if !enough_grams {
+            return Err(ActionResultCode::NotEnoughGrams);
         } else {
-            return Err(RESULT_CODE_NOT_ENOUGH_GRAMS);
+            return Err(ActionResultCode::NotEnoughExtra);
         }
     } else {
         // check the mode
         if (mode & RESERVE_ALL_BUT) != 0 {
             // need to reserve all but 'val' grams
             reserved = remaining.clone();
-            reserved.sub(&val).or(Err(RESULT_CODE_INVALID_BALANCE))?;
+            reserved.sub(&val).or(Err(ActionResultCode::InvalidBalance))?;
         } 
     }
 
-    remaining.sub(&reserved).or(Err(RESULT_CODE_INVALID_BALANCE))?;
+    remaining.sub(&reserved).or(Err(ActionResultCode::InvalidBalance))?;
     Ok(reserved)
 }
 
-fn setcode_action_handler(acc: &mut Account, code: Cell) -> Option<i32> {
+fn setcode_action_handler(acc: &mut Account, code: Cell) -> Option<ActionResultCode> {
     match acc.set_code(code) {
         true => None,
-        false => Some(RESULT_CODE_BAD_ACCOUNT_STATE)
+        false => Some(ActionResultCode::BadAccountState)
     }
 }
 
+/// Adds library to account state or removes it.
+/// Library is set by its code or by hash of its code if library is already present,
+/// in last case only its public flag is changed.
+fn change_library_action_handler(
+    acc: &mut Account,
+    mode: u8,
+    code: Option<Cell>,
+    hash: Option<UInt256>,
+) -> Option<ActionResultCode> {
+    if mode > SET_LIB_CODE_ADD_PUBLIC {
+        return Some(ActionResultCode::Unsupported);
+    }
+    let public = mode == SET_LIB_CODE_ADD_PUBLIC;
+    let result = match (code, hash) {
+        (Some(code), None) => if mode == SET_LIB_CODE_REMOVE {
+            acc.delete_library(&code.repr_hash())
+        } else {
+            acc.set_library(code, public)
+        },
+        (None, Some(hash)) => if mode == SET_LIB_CODE_REMOVE {
+            acc.delete_library(&hash)
+        } else {
+            acc.set_library_flag(&hash, public)
+        },
+        _ => return Some(ActionResultCode::ActionListInvalid)
+    };
+    match result {
+        true => None,
+        false => Some(ActionResultCode::BadAccountState)
+    }
+}
+
+/// Takes gas fees of computing phase from account state rolled back after aborted transaction.
+/// If account cannot pay the whole fees, all its grams are taken, and gas fees of the phase
+/// and total fees of the transaction are decreased to the really paid amount.
+pub fn charge_gas_fees(tr: &mut Transaction, acc: &mut Account, phase: &mut TrComputePhaseVm) -> Result<()> {
+    if acc.sub_funds(&CurrencyCollection::from_grams(phase.gas_fees.clone()))? {
+        return Ok(())
+    }
+    let paid = acc.get_balance().map(|balance| balance.grams.clone()).unwrap_or_default();
+    log::debug!(
+        target: "executor", 
+        "account cannot pay gas fees {}, only {} is taken", phase.gas_fees.0, paid.0
+    );
+    acc.sub_funds(&CurrencyCollection::from_grams(paid.clone()))?;
+    let mut unpaid = phase.gas_fees.clone();
+    unpaid.sub(&paid)?;
+    tr.total_fees_mut().sub(&CurrencyCollection::from_grams(unpaid))?;
+    phase.gas_fees = paid;
+    Ok(())
+}
+
 /// Implementation of transaction's bounce phase.
 /// Bounce phase occurs only if transaction 'aborted' flag is set and
 /// if inbound message is internal message with field 'bounce=true'.
 /// Generates outbound internal message for original message sender, with value equal
-/// to value of original message minus gas payments and forwarding fees 
-/// and empty body. Generated message is added to transaction's output message list.
+/// to remaining value of original message (after gas payments) minus forwarding fees.
+/// If `CapBounceMsgBody` capability is enabled, body of bounced message consists of
+/// 0xFFFFFFFF tag and first 256 bits of original message body, otherwise body is empty.
+/// Generated message is added to transaction's output message list.
+/// Gas fees of compute phase are already taken from remaining message value, so
+/// account balance is decreased by remaining message value (bounced value and forwarding fees).
+/// If account does not exist message value was not credited to it, so balance is not changed.
+/// Bounced message gets `lt` as creation lt, `lt` is incremented if message is created.
 pub fn bounce_phase(
     msg: Message,
-    _acc: &mut Account,
+    msg_balance: &CurrencyCollection,
+    acc: &mut Account,
     tr: &mut Transaction,
-    gas_fee: u64,
-    config: &MsgForwardPrices
-) -> Option<TrBouncePhase> {
+    lt: &mut u64,
+    config: &BlockchainConfig,
+) -> Result<Option<TrBouncePhase>> {
+    let address = acc.get_addr().cloned();
+    let phase_error = |cause: failure::Error| ExecutorError::phase_failed(TrPhase::Bounce, address.as_ref(), cause);
+    let fwd_prices = config.get_fwd_prices(&msg);
+    let body = msg.body();
     if let CommonMsgInfo::IntMsgInfo(msg) = msg.withdraw_header() {
         if msg.bounce {
-            // TODO: msg value must be initialized with msg_balance_remaining
-            let mut value = msg.value;
+            let mut value = msg_balance.clone();
             
             let msg_src = match msg.src {
                 MsgAddressIntOrNone::None => {
                     log::warn!(target: "executor", "invalid source address");
-                    return None
+                    return Ok(None)
                 }
                 MsgAddressIntOrNone::Some(addr) => addr
             };
@@ -800,37 +1319,54 @@ pub fn bounce_phase(
             header.ihr_disabled = true;
             header.bounce = false;
             header.bounced = true;
+            header.created_at = tr.now().into();
+            header.created_lt = *lt;
             let mut bounce_msg = Message::with_int_header(header.clone());
+            if config.has_capability(GlobalCapabilities::CapBounceMsgBody) {
+                let mut builder = BuilderData::new();
+                builder.append_u32(0xFFFFFFFF).map_err(phase_error)?;
+                if let Some(mut body) = body {
+                    let len = std::cmp::min(body.remaining_bits(), 256);
+                    let bits = body.get_next_bits(len).map_err(phase_error)?;
+                    builder.append_raw(&bits, len).map_err(phase_error)?;
+                }
+                bounce_msg.set_body(builder.into());
+            }
 
-            let (storage, fwd_full_fees) = config.calc_fwd_fee(&bounce_msg).unwrap();
-            let fwd_mine_fees = config.calc_mine_fee(fwd_full_fees);
+            let (storage, fwd_full_fees) = fwd_prices.calc_fwd_fee(&bounce_msg).map_err(phase_error)?;
+            let fwd_mine_fees = fwd_prices.calc_mine_fee(fwd_full_fees);
             let fwd_full_fees = fwd_full_fees;
 
-            // TODO: subtract msg balance from account balance (because it was added during
-            // credit phase)
-            let mut fee = Grams::from(gas_fee);
-            fee.0 += fwd_full_fees;
-            let phase_ok = value.grams.sub(&fee).unwrap();
+            let phase_ok = value.grams.sub(&fwd_full_fees.into()).map_err(phase_error)?;
 
-            if phase_ok {
+            //remaining msg balance was added to account during credit phase, take it back
+            //(there is no credit phase for not existing account, value is bounced as is)
+            let credited = *acc != Account::AccountNone;
+            if phase_ok && (!credited || acc.sub_funds(msg_balance).map_err(phase_error)?) {
+                log::debug!(target: "executor", "bounce msg value: {}, fwd fees: {}", value.grams, fwd_full_fees);
                 if let CommonMsgInfo::IntMsgInfo(header) = bounce_msg.header_mut() {
                     header.fwd_fee = (fwd_full_fees - fwd_mine_fees).into();
                     header.value = value;
                 }
-                tr.total_fees_mut().add(&CurrencyCollection::from_grams(fwd_mine_fees.into())).unwrap();
-                tr.add_out_message(&bounce_msg).unwrap();
-                Some(TrBouncePhase::Ok(
+                tr.total_fees_mut().add(&CurrencyCollection::from_grams(fwd_mine_fees.into())).map_err(phase_error)?;
+                tr.add_out_message(&bounce_msg).map_err(phase_error)?;
+                *lt += 1;
+                Ok(Some(TrBouncePhase::Ok(
                     TrBouncePhaseOk::with_params(storage, fwd_mine_fees.into(), fwd_full_fees.into())
-                ))
+                )))
             } else {
-                Some(TrBouncePhase::Nofunds(
+                log::debug!(target: "executor", "not enough funds to pay for bounce message");
+                Ok(Some(TrBouncePhase::Nofunds(
                     TrBouncePhaseNofunds::with_params(storage, fwd_full_fees.into())
-                ))
+                )))
             }
         } else {
-            None
+            Ok(None)
         }
     } else {
-        None
+        Ok(None)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+#[path = "tests/test_tr_phases.rs"]
+mod tests;
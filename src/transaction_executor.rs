@@ -14,14 +14,16 @@
 
 
 use num_traits::cast::ToPrimitive;
-use std::{sync::{atomic::{AtomicU64}, Arc}};
+use crate::{blockchain_config::{BlockchainConfig, GasConfigFull}, tr_phases::MAX_ACTIONS};
+
+use std::{cmp::{max, min}, sync::{atomic::{AtomicU64, Ordering}, Arc}};
 use ton_block::{
     Serializable,
     Account,
     MsgAddressInt, Message,
     Transaction,
 };
-use ton_types::{Cell, Result};
+use ton_types::{Cell, Result, UInt256};
 use ton_vm::{
     smart_contract_info::SmartContractInfo,
     stack::Stack,
@@ -29,11 +31,306 @@ use ton_vm::{
 
 
 
-pub trait StackBuilder {
+/// Builder of initial state of VM: stack and contract info (c7 register).
+/// Executors use default builders for standard contract entry points,
+/// custom builder can be set to run contracts with non-standard ones.
+pub trait StackBuilder: Send + Sync {
+    /// Build initial stack of VM for inbound message (if any) and account
+    fn build_stack(&self, in_msg: Option<&Message>, account: &Account) -> Result<Stack>;
+    /// Build c7 register contents: account info, block info, global config and rand seed.
+    /// Block time, lt and seed are taken from execution parameters
+    fn build_contract_info(
+        &self,
+        config: &BlockchainConfig,
+        acc: &Account,
+        acc_address: &MsgAddressInt,
+        tr_lt: u64,
+        params: &ExecuteParams,
+    ) -> SmartContractInfo {
+        let mut info = SmartContractInfo::with_myself(acc_address.write_to_new_cell().unwrap_or_default().into());
+        *info.block_lt_mut() = params.block_lt;
+        *info.trans_lt_mut() = tr_lt;
+        *info.unix_time_mut() = params.block_unixtime;
+        if let Some(balance) = acc.get_balance() {
+            // info.set_remaining_balance(balance.grams.value().to_u128().unwrap_or_default(), balance.other.clone());
+            *info.balance_remaining_grams_mut() = balance.grams.value().to_u128().unwrap_or_default();
+            *info.balance_remaining_other_mut() = balance.other_as_hashmap();
+        }
+        if let Some(data) = config.raw_config().config_params.data() {
+            info.set_config_params(data.clone());
+        }
+        // rand seed of transaction is sha256 of block seed and account address
+        info.calc_rand_seed(params.seed_block.clone(), &acc_address.address().get_bytestring(0));
+        info
+    }
+}
+
+/// Logical time interval of transaction.
+/// Transaction has `start` lt, its outbound messages have consecutive lts after it
+/// and `end` lt is the next one after the last outbound message lt.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TransactionLt {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl TransactionLt {
+    /// Calculate start lt of transaction. It must not be less than next free lt of block 
+    /// and end lt of the last account's transaction and must be greater than 
+    /// creation lt of inbound message.
+    pub fn calc_start_lt(block_next_lt: u64, acc_last_lt: u64, in_msg: Option<&Message>) -> u64 {
+        let msg_lt = in_msg.and_then(|msg| msg.lt()).map(|lt| lt + 1).unwrap_or_default();
+        max(max(block_next_lt, acc_last_lt), msg_lt)
+    }
+
+    /// Get lt interval of executed transaction
+    pub fn with_transaction(tr: &Transaction) -> Self {
+        let start = tr.logical_time();
+        TransactionLt {
+            start,
+            end: start + 1 + tr.outmsg_cnt as u64,
+        }
+    }
+
+    /// Atomically reserve lt interval of transaction from next free lt of block.
+    /// Count of outbound messages is unknown before execution, so interval for
+    /// the maximal count of them is reserved, unused part is returned by `LtReservation::commit`.
+    pub fn reserve(block_next_lt: &AtomicU64, acc_last_lt: u64, in_msg: Option<&Message>) -> LtReservation {
+        let mut current = block_next_lt.load(Ordering::SeqCst);
+        loop {
+            let start = Self::calc_start_lt(current, acc_last_lt, in_msg);
+            let end = start + 1 + MAX_ACTIONS as u64;
+            match block_next_lt.compare_exchange_weak(current, end, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => return LtReservation {
+                    block_next_lt,
+                    lt: TransactionLt { start, end },
+                    committed: false,
+                },
+                Err(actual) => current = actual
+            }
+        }
+    }
+}
+
+/// Lt interval reserved in block for transaction.
+/// If transaction is not executed, the whole interval is returned to block
+/// (only if no other transaction has reserved lts after it).
+pub struct LtReservation<'a> {
+    block_next_lt: &'a AtomicU64,
+    lt: TransactionLt,
+    committed: bool,
+}
+
+impl<'a> LtReservation<'a> {
+    /// Get start lt of transaction
+    pub fn start(&self) -> u64 {
+        self.lt.start
+    }
+
+    /// Set end lt of executed transaction and return unused lts to block
+    pub fn commit(mut self, end: u64) -> TransactionLt {
+        self.release(end);
+        self.committed = true;
+        TransactionLt { start: self.lt.start, end }
+    }
+
+    fn release(&self, end: u64) {
+        let _ = self.block_next_lt.compare_exchange(self.lt.end, end, Ordering::SeqCst, Ordering::SeqCst);
+    }
+}
+
+impl<'a> Drop for LtReservation<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.release(self.lt.start);
+        }
+    }
+}
+
+/// Gas accounting of block.
+/// Accumulates gas used by executed transactions and limits gas of further transactions
+/// so that total gas of block does not exceed block gas limit. Transaction reserves
+/// gas before running VM (see `BlockGasReservation`), so counter can be shared
+/// by executors running in parallel.
+#[derive(Debug)]
+pub struct BlockGasCounter {
+    limit: u64,
+    used: AtomicU64,
+}
+
+impl BlockGasCounter {
+    pub fn new(limit: u64) -> Self {
+        BlockGasCounter {
+            limit,
+            used: AtomicU64::new(0),
+        }
+    }
+
+    /// Create counter with `block_gas_limit` from gas config
+    pub fn with_config(config: &GasConfigFull) -> Self {
+        Self::new(config.block_gas_limit)
+    }
+
+    /// Get block gas limit
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Get gas used by executed transactions and reserved by running ones
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Get gas which can be used by further transactions
+    pub fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.used())
+    }
+
+    /// Check if block gas limit is reached
+    pub fn is_exhausted(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Start gas reservation of transaction, nothing is reserved yet
+    pub fn reservation(&self) -> BlockGasReservation<'_> {
+        BlockGasReservation {
+            block_gas: self,
+            reserved: 0,
+            committed: false,
+        }
+    }
+}
+
+/// Gas reserved in block for transaction.
+/// Unused part of reserved gas is returned to block by `commit`, if transaction
+/// is not executed (reservation is dropped) the whole reserved gas is returned.
+pub struct BlockGasReservation<'a> {
+    block_gas: &'a BlockGasCounter,
+    reserved: u64,
+    committed: bool,
+}
+
+impl<'a> BlockGasReservation<'a> {
+    /// Atomically reserve `gas` from remaining block gas, if block has less gas
+    /// all remaining gas is reserved. Returns reserved amount
+    pub fn reserve(&mut self, gas: u64) -> u64 {
+        let used = &self.block_gas.used;
+        let mut current = used.load(Ordering::SeqCst);
+        loop {
+            let reserved = min(gas, self.block_gas.limit.saturating_sub(current));
+            match used.compare_exchange_weak(current, current + reserved, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => {
+                    self.reserved += reserved;
+                    return reserved
+                }
+                Err(actual) => current = actual
+            }
+        }
+    }
+
+    /// Get gas reserved by transaction
+    pub fn reserved(&self) -> u64 {
+        self.reserved
+    }
+
+    /// Set gas used by executed transaction and return unused gas to block.
+    /// Gas used above reserved amount (i.e. by special account) is added to block gas
+    pub fn commit(mut self, gas_used: u64) {
+        if gas_used > self.reserved {
+            self.block_gas.used.fetch_add(gas_used - self.reserved, Ordering::SeqCst);
+        } else {
+            self.release(self.reserved - gas_used);
+        }
+        self.committed = true;
+    }
+
+    fn release(&self, gas: u64) {
+        self.block_gas.used.fetch_sub(gas, Ordering::SeqCst);
+    }
+}
+
+impl<'a> Drop for BlockGasReservation<'a> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.release(self.reserved);
+        }
+    }
+}
+
+/// Parameters of transaction execution: block context and account history.
+/// New optional parameters are added here with default values,
+/// so executor interface is not changed.
+#[derive(Clone, Debug, Default)]
+pub struct ExecuteParams {
+    /// Hash of the last account's transaction (zero for the first one)
+    pub prev_trans_hash: UInt256,
+    /// Logical time of the last account's transaction (zero for the first one)
+    pub prev_trans_lt: u64,
+    pub block_unixtime: u32,
+    pub block_lt: u64,
+    /// Random seed of block, rand seed of transaction is derived from it
+    pub seed_block: UInt256,
+    /// Next free lt of block, lt interval of transaction is reserved from it atomically
+    /// (see `TransactionLt::reserve`), so it can be shared by executors running in parallel
+    pub last_tr_lt: Arc<AtomicU64>,
+    /// Gas used in block, transaction gas is limited by remaining block gas.
+    /// Gas is reserved before VM runs and unused gas is returned after execution.
+    /// If block gas is exhausted, message is not processed and execution fails
+    /// with `ExecutorError::BlockGasLimitReached`
+    pub block_gas: Option<Arc<BlockGasCounter>>,
+    /// Trace VM execution
+    pub debug: bool,
+}
+
+impl ExecuteParams {
+    /// Create parameters for block with given time and lt, other parameters have default values
+    pub fn with_block(block_unixtime: u32, block_lt: u64, last_tr_lt: Arc<AtomicU64>) -> Self {
+        ExecuteParams {
+            block_unixtime,
+            block_lt,
+            last_tr_lt,
+            ..Default::default()
+        }
+    }
+
+    /// Sets hash and lt of the last account's transaction
+    pub fn set_prev_trans(mut self, prev_trans_hash: UInt256, prev_trans_lt: u64) -> Self {
+        self.prev_trans_hash = prev_trans_hash;
+        self.prev_trans_lt = prev_trans_lt;
+        self
+    }
+
+    /// Sets random seed of block
+    pub fn set_seed_block(mut self, seed_block: UInt256) -> Self {
+        self.seed_block = seed_block;
+        self
+    }
+
+    /// Sets gas counter of block
+    pub fn set_block_gas(mut self, block_gas: Arc<BlockGasCounter>) -> Self {
+        self.block_gas = Some(block_gas);
+        self
+    }
 
+    /// Sets trace flag to TVM
+    pub fn set_debug(mut self, debug: bool) -> Self {
+        self.debug = debug;
+        self
+    }
 }
 
 pub trait TransactionExecutor {
+    /// Create and execute transaction for account with given parameters.
+    /// Returns transaction and its lt interval.
+    fn execute_with_params(
+        &self,
+        in_msg: Option<&Message>,
+        account_root: &mut Cell,
+        params: ExecuteParams,
+    ) -> Result<(Transaction, TransactionLt)>;
+    /// Create and execute transaction for account.
+    /// Compatibility wrapper for `execute_with_params`, parameters which are not passed
+    /// have default values (see `ExecuteParams`).
     fn execute(
         &self,
         in_msg: Option<&Message>,
@@ -42,18 +339,8 @@ pub trait TransactionExecutor {
         block_lt: u64,
         last_tr_lt: Arc<AtomicU64>,
         debug: bool
-    ) -> Result<Transaction>;
-    fn build_contract_info(&self, acc: &Account, acc_address: &MsgAddressInt, block_unixtime: u32, block_lt: u64, tr_lt: u64) -> SmartContractInfo {
-        let mut info = SmartContractInfo::with_myself(acc_address.write_to_new_cell().unwrap_or_default().into());
-        *info.block_lt_mut() = block_lt;
-        *info.trans_lt_mut() = tr_lt;
-        *info.unix_time_mut() = block_unixtime;
-        if let Some(balance) = acc.get_balance() {
-            // info.set_remaining_balance(balance.grams.value().to_u128().unwrap_or_default(), balance.other.clone());
-            *info.balance_remaining_grams_mut() = balance.grams.value().to_u128().unwrap_or_default();
-            *info.balance_remaining_other_mut() = balance.other_as_hashmap();
-        }
-        info
+    ) -> Result<Transaction> {
+        let params = ExecuteParams::with_block(block_unixtime, block_lt, last_tr_lt).set_debug(debug);
+        self.execute_with_params(in_msg, account_root, params).map(|(tr, _)| tr)
     }
-    fn build_stack(&self, in_msg: Option<&Message>, account: &Account) -> Stack;
 }
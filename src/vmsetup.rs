@@ -12,7 +12,7 @@
 * limitations under the License.
 */
 
-use ton_types::{Cell, SliceData};
+use ton_types::{Cell, Result, SliceData};
 use ton_vm::{
     executor::{Engine, gas::gas_state::Gas}, smart_contract_info::SmartContractInfo,
     stack::{Stack, StackItem, savelist::SaveList}
@@ -42,15 +42,15 @@ impl VMSetup {
     }
 
     /// Sets SmartContractInfo for TVM register c7
-    pub fn set_contract_info(mut self, sci: &SmartContractInfo) -> VMSetup {
-        self.ctrls.put(7, &mut sci.into_temp_data()).unwrap();
-        self
+    pub fn set_contract_info(mut self, sci: &SmartContractInfo) -> Result<VMSetup> {
+        self.ctrls.put(7, &mut sci.into_temp_data())?;
+        Ok(self)
     }
 
     /// Sets persistent data for contract in register c4
-    pub fn set_data(mut self, data: Cell) -> VMSetup {
-        self.ctrls.put(4, &mut StackItem::Cell(data)).unwrap();
-        self
+    pub fn set_data(mut self, data: Cell) -> Result<VMSetup> {
+        self.ctrls.put(4, &mut StackItem::Cell(data))?;
+        Ok(self)
     }
 
     /// Sets initial stack for TVM
@@ -0,0 +1,78 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+use std::{sync::{Arc, Barrier}, thread};
+use ton_executor::BlockGasCounter;
+
+#[test]
+fn test_reservation_is_limited_by_remaining_gas() {
+    let block_gas = BlockGasCounter::new(1000);
+    let mut first = block_gas.reservation();
+    assert_eq!(first.reserve(700), 700);
+    let mut second = block_gas.reservation();
+    assert_eq!(second.reserve(700), 300);
+    assert!(block_gas.is_exhausted());
+    assert_eq!(second.reserve(1), 0);
+    assert_eq!(second.reserved(), 300);
+}
+
+#[test]
+fn test_unused_gas_is_returned_on_commit() {
+    let block_gas = BlockGasCounter::new(1000);
+    let mut reservation = block_gas.reservation();
+    reservation.reserve(700);
+    reservation.commit(200);
+    assert_eq!(block_gas.used(), 200);
+    assert_eq!(block_gas.remaining(), 800);
+}
+
+#[test]
+fn test_gas_used_above_reservation_is_added_on_commit() {
+    let block_gas = BlockGasCounter::new(1000);
+    // special accounts do not reserve gas
+    block_gas.reservation().commit(300);
+    assert_eq!(block_gas.used(), 300);
+}
+
+#[test]
+fn test_reservation_is_returned_if_not_committed() {
+    let block_gas = BlockGasCounter::new(1000);
+    {
+        let mut reservation = block_gas.reservation();
+        reservation.reserve(700);
+        assert_eq!(block_gas.remaining(), 300);
+    }
+    assert_eq!(block_gas.used(), 0);
+}
+
+#[test]
+fn test_parallel_reservations_do_not_exceed_limit() {
+    let block_gas = Arc::new(BlockGasCounter::new(1000));
+    let barrier = Arc::new(Barrier::new(8));
+    let threads: Vec<_> = (0..8).map(|_| {
+        let block_gas = block_gas.clone();
+        let barrier = barrier.clone();
+        thread::spawn(move || {
+            let mut reservation = block_gas.reservation();
+            let reserved = reservation.reserve(300);
+            // all reservations are held at the same time
+            barrier.wait();
+            reservation.commit(reserved);
+            reserved
+        })
+    }).collect();
+    let reserved: u64 = threads.into_iter().map(|handle| handle.join().unwrap()).sum();
+    assert_eq!(reserved, 1000);
+    assert_eq!(block_gas.used(), 1000);
+}
@@ -0,0 +1,344 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
+use ton_block::{
+    Account, AccountState, AddSub, CommonMsgInfo, CurrencyCollection, Deserializable, Grams, InternalMessageHeader,
+    Message, MsgAddressInt, MsgAddressIntOrNone, Serializable, StateInit, Transaction, TransactionDescr,
+    TransactionDescrOrdinary, TrBouncePhase, TrComputePhase, AccStatusChange, ComputeSkipReason,
+    SENDMSG_ALL_BALANCE, SENDMSG_DELETE_IF_EMPTY, SENDMSG_REMAINING_MSG_BALANCE,
+};
+use ton_executor::{
+    BlockchainConfig, BlockGasCounter, ExecuteParams, ExecutorError, OrdinaryTransactionExecutor, TransactionExecutor,
+};
+use ton_types::{AccountId, BuilderData, Cell};
+
+const BLOCK_UT: u32 = 1576526553;
+const BLOCK_LT: u64 = 2_000_000_000;
+const MSG_VALUE: u64 = 1_000_000_000;
+const ACCOUNT_BALANCE: u64 = 10_000_000_000;
+
+fn address(byte: u8) -> MsgAddressInt {
+    MsgAddressInt::with_standart(None, 0, AccountId::from([byte; 32])).unwrap()
+}
+
+fn bounceable_message(dst: MsgAddressInt) -> Message {
+    let mut header = InternalMessageHeader::with_addresses(
+        address(0x11),
+        dst,
+        CurrencyCollection::with_grams(MSG_VALUE),
+    );
+    header.bounce = true;
+    header.created_lt = BLOCK_LT - 1;
+    Message::with_int_header(header)
+}
+
+fn non_bounceable_message(dst: MsgAddressInt) -> Message {
+    let mut msg = bounceable_message(dst);
+    if let CommonMsgInfo::IntMsgInfo(header) = msg.header_mut() {
+        header.bounce = false;
+    }
+    msg
+}
+
+/// Outbound message from contract to `dst` with no value of its own
+fn outbound_message(dst: MsgAddressInt) -> Message {
+    let mut header = InternalMessageHeader::with_addresses(address(0x22), dst, CurrencyCollection::default());
+    header.src = MsgAddressIntOrNone::None;
+    header.ihr_disabled = true;
+    Message::with_int_header(header)
+}
+
+/// Code of contract which sends message `msg` with `mode` on every inbound message:
+/// PUSHREF; PUSHINT mode; SENDRAWMSG
+fn sending_contract_code(mode: u8, msg: &Message) -> Cell {
+    let mut builder = BuilderData::new();
+    builder.append_raw(&[0x88, 0x81, 0x00, mode, 0xFB, 0x00], 48).unwrap();
+    builder.append_reference_cell(msg.write_to_new_cell().unwrap().into());
+    builder.into()
+}
+
+/// Active account which has paid storage fees up to block time
+fn active_account(address: &MsgAddressInt, balance: u64, code: Cell) -> Account {
+    let mut account = Account::with_address_and_ballance(address, &CurrencyCollection::with_grams(balance));
+    let mut state_init = StateInit::default();
+    state_init.code = Some(code);
+    state_init.data = Some(Cell::default());
+    account.activate(state_init);
+    account.set_last_paid(BLOCK_UT);
+    account.update_storage_stat().unwrap();
+    account
+}
+
+fn execute(msg: &Message, account_root: &mut Cell) -> Transaction {
+    let executor = OrdinaryTransactionExecutor::new(BlockchainConfig::default());
+    let params = ExecuteParams::with_block(BLOCK_UT, BLOCK_LT, Arc::new(AtomicU64::new(BLOCK_LT)));
+    executor.execute_with_params(Some(msg), account_root, params).unwrap().0
+}
+
+fn ordinary_description(tr: &Transaction) -> TransactionDescrOrdinary {
+    match tr.read_description().unwrap() {
+        TransactionDescr::Ordinary(description) => description,
+        _ => panic!("transaction must be ordinary")
+    }
+}
+
+fn out_messages(tr: &Transaction) -> Vec<Message> {
+    let mut msgs = vec![];
+    tr.iterate_out_msgs(|msg| {
+        msgs.push(msg);
+        Ok(true)
+    }).unwrap();
+    msgs
+}
+
+/// Checks that transaction is aborted and the whole value of inbound message
+/// (except forwarding fees) is bounced back to sender.
+fn check_bounced(tr: &Transaction, src: &MsgAddressInt) {
+    let description = match tr.read_description().unwrap() {
+        TransactionDescr::Ordinary(description) => description,
+        _ => panic!("transaction must be ordinary")
+    };
+    assert!(description.aborted);
+    let fwd_fees = match description.bounce {
+        Some(TrBouncePhase::Ok(ref phase)) => phase.fwd_fees.clone(),
+        ref bounce => panic!("bounce phase must be successful, got {:?}", bounce)
+    };
+
+    let msgs = out_messages(tr);
+    assert_eq!(msgs.len(), 1);
+    match msgs[0].header() {
+        CommonMsgInfo::IntMsgInfo(header) => {
+            assert!(header.bounced);
+            assert!(!header.bounce);
+            assert_eq!(&header.dst, src);
+            let mut expected = Grams::from(MSG_VALUE);
+            expected.sub(&fwd_fees).unwrap();
+            assert_eq!(header.value.grams, expected);
+        }
+        _ => panic!("bounced message must be internal")
+    }
+}
+
+#[test]
+fn test_bounce_message_to_missing_account() {
+    let mut account_root: Cell = Account::AccountNone.write_to_new_cell().unwrap().into();
+    let msg = bounceable_message(address(0x22));
+
+    let tr = execute(&msg, &mut account_root);
+
+    check_bounced(&tr, &address(0x11));
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert_eq!(account, Account::AccountNone);
+}
+
+#[test]
+fn test_bounce_message_with_bad_state_init_to_missing_account() {
+    let mut account_root: Cell = Account::AccountNone.write_to_new_cell().unwrap().into();
+    // StateInit hash does not match destination address
+    let mut msg = bounceable_message(address(0x22));
+    msg.set_state_init(StateInit::default());
+
+    let tr = execute(&msg, &mut account_root);
+
+    check_bounced(&tr, &address(0x11));
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert_eq!(account, Account::AccountNone);
+}
+
+#[test]
+fn test_uninit_account_without_funds_is_destroyed() {
+    let mut account = Account::with_address_and_ballance(&address(0x22), &CurrencyCollection::default());
+    // storage was never paid, so uninitialized account cannot pay storage fees
+    account.update_storage_stat().unwrap();
+    let mut account_root: Cell = account.write_to_new_cell().unwrap().into();
+    let msg = bounceable_message(address(0x22));
+
+    let tr = execute(&msg, &mut account_root);
+
+    check_bounced(&tr, &address(0x11));
+    match tr.read_description().unwrap() {
+        TransactionDescr::Ordinary(description) => assert!(description.destroyed),
+        _ => panic!("transaction must be ordinary")
+    }
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert_eq!(account, Account::AccountNone);
+}
+#[test]
+fn test_new_account_pays_storage_since_creation() {
+    let mut account_root: Cell = Account::AccountNone.write_to_new_cell().unwrap().into();
+    let mut msg = bounceable_message(address(0x22));
+    if let CommonMsgInfo::IntMsgInfo(header) = msg.header_mut() {
+        header.bounce = false;
+    }
+
+    execute(&msg, &mut account_root);
+
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert_eq!(account.storage_info().unwrap().last_paid, BLOCK_UT);
+}
+
+#[test]
+fn test_transaction_lt_interval() {
+    let executor = OrdinaryTransactionExecutor::new(BlockchainConfig::default());
+    let block_next_lt = Arc::new(AtomicU64::new(BLOCK_LT));
+    let mut msg = bounceable_message(address(0x22));
+    if let CommonMsgInfo::IntMsgInfo(header) = msg.header_mut() {
+        header.created_lt = BLOCK_LT + 10;
+    }
+    let mut account_root: Cell = Account::AccountNone.write_to_new_cell().unwrap().into();
+    let params = ExecuteParams::with_block(BLOCK_UT, BLOCK_LT, block_next_lt.clone());
+
+    let (tr, lt) = executor.execute_with_params(Some(&msg), &mut account_root, params).unwrap();
+
+    // transaction lt is greater than creation lt of inbound message
+    assert_eq!(lt.start, BLOCK_LT + 11);
+    assert_eq!(tr.logical_time(), lt.start);
+    // bounced message is the only outbound message
+    assert_eq!(lt.end, lt.start + 2);
+    assert_eq!(block_next_lt.load(Ordering::SeqCst), lt.end);
+}
+
+#[test]
+fn test_remaining_msg_balance_is_forwarded_without_gas_fees() {
+    let code = sending_contract_code(SENDMSG_REMAINING_MSG_BALANCE, &outbound_message(address(0x33)));
+    let account = active_account(&address(0x22), ACCOUNT_BALANCE, code);
+    let mut account_root: Cell = account.write_to_new_cell().unwrap().into();
+    let msg = non_bounceable_message(address(0x22));
+
+    let tr = execute(&msg, &mut account_root);
+
+    let description = ordinary_description(&tr);
+    assert!(!description.aborted);
+    let gas_fees = match description.compute_ph {
+        TrComputePhase::Vm(ref phase) => phase.gas_fees.clone(),
+        ref phase => panic!("compute phase must be executed, got {:?}", phase)
+    };
+    let fwd_fees = description.action.as_ref().unwrap().total_fwd_fees.clone().unwrap();
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    let mut expected = Grams::from(MSG_VALUE);
+    expected.sub(&gas_fees).unwrap();
+    expected.sub(&fwd_fees).unwrap();
+    assert_eq!(msgs[0].get_value().unwrap().grams, expected);
+    // account pays nothing: gas and forwarding fees are paid from inbound value
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert_eq!(account.get_balance().unwrap().grams, Grams::from(ACCOUNT_BALANCE));
+}
+
+#[test]
+fn test_account_sending_all_balance_is_deleted() {
+    let code = sending_contract_code(SENDMSG_ALL_BALANCE | SENDMSG_DELETE_IF_EMPTY, &outbound_message(address(0x33)));
+    let account = active_account(&address(0x22), ACCOUNT_BALANCE, code);
+    let mut account_root: Cell = account.write_to_new_cell().unwrap().into();
+    let old_hash = account_root.repr_hash();
+    let msg = bounceable_message(address(0x22));
+
+    let tr = execute(&msg, &mut account_root);
+
+    let description = ordinary_description(&tr);
+    assert!(!description.aborted);
+    assert!(description.destroyed);
+    assert_eq!(description.action.as_ref().unwrap().status_change, AccStatusChange::Deleted);
+    assert_eq!(out_messages(&tr).len(), 1);
+    let account_none_root: Cell = Account::AccountNone.write_to_new_cell().unwrap().into();
+    assert_eq!(account_root, account_none_root);
+    let state_update = tr.read_state_update().unwrap();
+    assert_eq!(state_update.old_hash, old_hash);
+    assert_eq!(state_update.new_hash, account_none_root.repr_hash());
+}
+
+#[test]
+fn test_small_value_to_indebted_uninit_account_is_spent_on_storage() {
+    let mut account = Account::with_address_and_ballance(&address(0x22), &CurrencyCollection::default());
+    // storage was never paid, so message value is not enough to pay storage fees
+    account.update_storage_stat().unwrap();
+    let mut account_root: Cell = account.write_to_new_cell().unwrap().into();
+    let value = 1000;
+    let mut msg = non_bounceable_message(address(0x22));
+    if let CommonMsgInfo::IntMsgInfo(header) = msg.header_mut() {
+        header.value = CurrencyCollection::with_grams(value);
+    }
+
+    let tr = execute(&msg, &mut account_root);
+
+    let description = ordinary_description(&tr);
+    assert!(description.credit_first);
+    assert!(description.destroyed);
+    match description.compute_ph {
+        TrComputePhase::Skipped(ref skipped) => assert_eq!(skipped.reason, ComputeSkipReason::NoState),
+        ref phase => panic!("compute phase must be skipped, got {:?}", phase)
+    }
+    assert!(description.bounce.is_none());
+    assert!(out_messages(&tr).is_empty());
+    // the whole value is taken as storage fees and account is not created again
+    assert_eq!(tr.total_fees().grams, Grams::from(value));
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert_eq!(account, Account::AccountNone);
+}
+
+#[test]
+fn test_non_bounceable_message_with_bad_state_init_to_missing_account() {
+    let mut account_root: Cell = Account::AccountNone.write_to_new_cell().unwrap().into();
+    // StateInit hash does not match destination address
+    let mut msg = non_bounceable_message(address(0x22));
+    msg.set_state_init(StateInit::default());
+
+    let tr = execute(&msg, &mut account_root);
+
+    let description = ordinary_description(&tr);
+    assert!(description.aborted);
+    match description.compute_ph {
+        TrComputePhase::Skipped(ref skipped) => assert_eq!(skipped.reason, ComputeSkipReason::BadState),
+        ref phase => panic!("compute phase must be skipped, got {:?}", phase)
+    }
+    assert!(description.bounce.is_none());
+    assert!(out_messages(&tr).is_empty());
+    // value is kept on uninitialized account
+    let account = Account::construct_from(&mut account_root.into()).unwrap();
+    assert!(matches!(account.state(), Some(AccountState::AccountUninit)));
+    assert_eq!(account.get_balance().unwrap().grams, Grams::from(MSG_VALUE));
+}
+
+#[test]
+fn test_block_gas_is_taken_by_executed_transaction() {
+    let executor = OrdinaryTransactionExecutor::new(BlockchainConfig::default());
+    let code = sending_contract_code(SENDMSG_REMAINING_MSG_BALANCE, &outbound_message(address(0x33)));
+    let account = active_account(&address(0x22), ACCOUNT_BALANCE, code);
+    let msg = non_bounceable_message(address(0x22));
+
+    // block gas is exhausted, message is not processed and account is not changed
+    let block_gas = Arc::new(BlockGasCounter::new(0));
+    let mut account_root: Cell = account.write_to_new_cell().unwrap().into();
+    let params = ExecuteParams::with_block(BLOCK_UT, BLOCK_LT, Arc::new(AtomicU64::new(BLOCK_LT)))
+        .set_block_gas(block_gas.clone());
+    let err = executor.execute_with_params(Some(&msg), &mut account_root, params).unwrap_err();
+    match err.downcast_ref::<ExecutorError>() {
+        Some(ExecutorError::BlockGasLimitReached) => (),
+        _ => panic!("execution must fail with BlockGasLimitReached, got {}", err)
+    }
+    assert_eq!(account_root, Cell::from(account.write_to_new_cell().unwrap()));
+
+    // only gas used by transaction is taken from block
+    let block_gas = Arc::new(BlockGasCounter::new(10_000_000));
+    let params = ExecuteParams::with_block(BLOCK_UT, BLOCK_LT, Arc::new(AtomicU64::new(BLOCK_LT)))
+        .set_block_gas(block_gas.clone());
+    let (tr, _) = executor.execute_with_params(Some(&msg), &mut account_root, params).unwrap();
+    let gas_used = match ordinary_description(&tr).compute_ph {
+        TrComputePhase::Vm(ref phase) => phase.gas_used.0,
+        ref phase => panic!("compute phase must be executed, got {:?}", phase)
+    };
+    assert!(gas_used > 0);
+    assert_eq!(block_gas.used(), gas_used);
+}
@@ -0,0 +1,259 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+use std::sync::{atomic::AtomicU64, Arc};
+use ton_block::{
+    Account, AccountState, CommonMsgInfo, CurrencyCollection, Deserializable, Grams, Message, MsgAddressInt,
+    MsgAddressIntOrNone, Serializable, SplitMergeInfo, StateInit, Transaction, TransactionDescr,
+};
+use ton_executor::{
+    BlockchainConfig, ExecuteParams, MergeInstallTransactionExecutor, MergePrepareTransactionExecutor,
+    SplitInstallTransactionExecutor, SplitPrepareTransactionExecutor, TransactionExecutor,
+};
+use ton_types::{AccountId, Cell, UInt256};
+
+const BLOCK_UT: u32 = 1576526553;
+const BLOCK_LT: u64 = 2_000_000_000;
+// odd balance checks that odd nanogram stays on account after split
+const ACCOUNT_BALANCE: u64 = 10_000_000_001;
+const THIS_ADDR: [u8; 32] = [0x22; 32];
+
+/// Sibling address differs from `THIS_ADDR` in the first bit only
+fn sibling_addr() -> [u8; 32] {
+    let mut addr = THIS_ADDR;
+    addr[0] ^= 0x80;
+    addr
+}
+
+fn address(addr: [u8; 32]) -> MsgAddressInt {
+    MsgAddressInt::with_standart(None, 0, AccountId::from(addr)).unwrap()
+}
+
+/// Split info of account split or merged with the shard of zero prefix length
+fn split_info(this_addr: [u8; 32], sibling_addr: [u8; 32]) -> SplitMergeInfo {
+    SplitMergeInfo {
+        cur_shard_pfx_len: 0,
+        acc_split_depth: 1,
+        this_addr: UInt256::from(this_addr),
+        sibling_addr: UInt256::from(sibling_addr),
+    }
+}
+
+/// Active account which has paid storage fees up to block time
+fn active_account(addr: [u8; 32], balance: u64) -> Account {
+    let mut account = Account::with_address_and_ballance(&address(addr), &CurrencyCollection::with_grams(balance));
+    let mut state_init = StateInit::default();
+    state_init.code = Some(Cell::default());
+    state_init.data = Some(Cell::default());
+    account.activate(state_init);
+    account.set_last_paid(BLOCK_UT);
+    account.update_storage_stat().unwrap();
+    account
+}
+
+fn state_init(account: &Account) -> Option<&StateInit> {
+    match account.state() {
+        Some(AccountState::AccountActive(state_init)) => Some(state_init),
+        _ => None
+    }
+}
+
+fn params() -> ExecuteParams {
+    ExecuteParams::with_block(BLOCK_UT, BLOCK_LT, Arc::new(AtomicU64::new(BLOCK_LT)))
+}
+
+fn account_root(account: &Account) -> Cell {
+    account.write_to_new_cell().unwrap().into()
+}
+
+fn read_account(account_root: &Cell) -> Account {
+    Account::construct_from(&mut account_root.clone().into()).unwrap()
+}
+
+fn balance(account_root: &Cell) -> Grams {
+    read_account(account_root).get_balance().unwrap().grams.clone()
+}
+
+fn out_messages(tr: &Transaction) -> Vec<Message> {
+    let mut msgs = vec![];
+    tr.iterate_out_msgs(|msg| {
+        msgs.push(msg);
+        Ok(true)
+    }).unwrap();
+    msgs
+}
+
+fn check_sibling_message(msg: &Message, grams: u64) {
+    match msg.header() {
+        CommonMsgInfo::IntMsgInfo(header) => {
+            assert!(!header.bounce);
+            assert_eq!(header.src, MsgAddressIntOrNone::Some(address(THIS_ADDR)));
+            assert_eq!(header.dst, address(sibling_addr()));
+            assert_eq!(header.value.grams, Grams::from(grams));
+            assert_eq!(header.fwd_fee, Grams::default());
+        }
+        _ => panic!("message to sibling must be internal")
+    }
+}
+
+fn split_prepare(account_root: &mut Cell) -> Transaction {
+    let executor = SplitPrepareTransactionExecutor::new(
+        BlockchainConfig::default(), split_info(THIS_ADDR, sibling_addr())
+    );
+    executor.execute_with_params(None, account_root, params()).unwrap().0
+}
+
+fn merge_prepare(account_root: &mut Cell) -> Transaction {
+    let executor = MergePrepareTransactionExecutor::new(
+        BlockchainConfig::default(), split_info(THIS_ADDR, sibling_addr())
+    );
+    executor.execute_with_params(None, account_root, params()).unwrap().0
+}
+
+#[test]
+fn test_split_prepare_sends_half_of_balance_with_state() {
+    let account = active_account(THIS_ADDR, ACCOUNT_BALANCE);
+    let mut account_root = account_root(&account);
+
+    let tr = split_prepare(&mut account_root);
+
+    match tr.read_description().unwrap() {
+        TransactionDescr::SplitPrepare(description) => {
+            assert!(!description.aborted);
+            assert!(!description.destroyed);
+            assert_eq!(description.action.unwrap().msgs_created, 1);
+        }
+        _ => panic!("transaction must be split prepare")
+    }
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    check_sibling_message(&msgs[0], ACCOUNT_BALANCE / 2);
+    assert_eq!(msgs[0].state_init(), state_init(&account));
+    assert_eq!(balance(&account_root), Grams::from(ACCOUNT_BALANCE - ACCOUNT_BALANCE / 2));
+}
+
+#[test]
+fn test_split_prepare_of_uninit_account_is_aborted() {
+    let mut account = Account::with_address_and_ballance(
+        &address(THIS_ADDR), &CurrencyCollection::with_grams(ACCOUNT_BALANCE)
+    );
+    account.set_last_paid(BLOCK_UT);
+    let mut account_root = account_root(&account);
+
+    let tr = split_prepare(&mut account_root);
+
+    match tr.read_description().unwrap() {
+        TransactionDescr::SplitPrepare(description) => {
+            assert!(description.aborted);
+            assert!(description.action.is_none());
+        }
+        _ => panic!("transaction must be split prepare")
+    }
+    assert!(out_messages(&tr).is_empty());
+    assert_eq!(balance(&account_root), Grams::from(ACCOUNT_BALANCE));
+}
+
+#[test]
+fn test_split_install_creates_sibling_account() {
+    let account = active_account(THIS_ADDR, ACCOUNT_BALANCE);
+    let prepare_transaction = split_prepare(&mut account_root(&account));
+
+    let executor = SplitInstallTransactionExecutor::new(
+        BlockchainConfig::default(), split_info(sibling_addr(), THIS_ADDR), prepare_transaction
+    );
+    let mut account_root = account_root(&Account::AccountNone);
+    let (tr, lt) = executor.execute_with_params(None, &mut account_root, params()).unwrap();
+
+    match tr.read_description().unwrap() {
+        TransactionDescr::SplitInstall(description) => assert!(description.installed),
+        _ => panic!("transaction must be split install")
+    }
+    // install transaction is executed after message from prepare transaction is created
+    assert!(lt.start > BLOCK_LT + 1);
+    let installed = read_account(&account_root);
+    assert_eq!(installed.get_addr(), Some(&address(sibling_addr())));
+    assert_eq!(installed.get_balance().unwrap().grams, Grams::from(ACCOUNT_BALANCE / 2));
+    assert!(state_init(&installed).is_some());
+    assert_eq!(state_init(&installed), state_init(&account));
+}
+
+#[test]
+fn test_split_install_to_existing_account_fails() {
+    let account = active_account(THIS_ADDR, ACCOUNT_BALANCE);
+    let prepare_transaction = split_prepare(&mut account_root(&account));
+
+    let executor = SplitInstallTransactionExecutor::new(
+        BlockchainConfig::default(), split_info(sibling_addr(), THIS_ADDR), prepare_transaction
+    );
+    let mut account_root = account_root(&active_account(sibling_addr(), ACCOUNT_BALANCE));
+    assert!(executor.execute_with_params(None, &mut account_root, params()).is_err());
+}
+
+#[test]
+fn test_merge_prepare_sends_whole_balance_and_removes_account() {
+    let mut account_root = account_root(&active_account(THIS_ADDR, ACCOUNT_BALANCE));
+
+    let tr = merge_prepare(&mut account_root);
+
+    match tr.read_description().unwrap() {
+        TransactionDescr::MergePrepare(description) => assert!(!description.aborted),
+        _ => panic!("transaction must be merge prepare")
+    }
+    let msgs = out_messages(&tr);
+    assert_eq!(msgs.len(), 1);
+    check_sibling_message(&msgs[0], ACCOUNT_BALANCE);
+    assert!(msgs[0].state_init().is_none());
+    assert_eq!(read_account(&account_root), Account::AccountNone);
+}
+
+#[test]
+fn test_merge_install_credits_sibling_account() {
+    let prepare_transaction = merge_prepare(&mut account_root(&active_account(THIS_ADDR, ACCOUNT_BALANCE)));
+
+    let executor = MergeInstallTransactionExecutor::new(
+        BlockchainConfig::default(), split_info(sibling_addr(), THIS_ADDR), prepare_transaction
+    );
+    let mut account_root = account_root(&active_account(sibling_addr(), ACCOUNT_BALANCE));
+    let tr = executor.execute_with_params(None, &mut account_root, params()).unwrap().0;
+
+    match tr.read_description().unwrap() {
+        TransactionDescr::MergeInstall(description) => {
+            assert!(!description.aborted);
+            assert!(!description.destroyed);
+            assert!(description.credit_ph.is_some());
+            assert!(description.action.is_none());
+        }
+        _ => panic!("transaction must be merge install")
+    }
+    assert_eq!(balance(&account_root), Grams::from(2 * ACCOUNT_BALANCE));
+}
+
+#[test]
+fn test_invalid_split_info_is_rejected() {
+    let mut account_root = account_root(&active_account(THIS_ADDR, ACCOUNT_BALANCE));
+
+    // addresses differ in the bit which is not next to shard prefix
+    let mut wrong_addr = THIS_ADDR;
+    wrong_addr[0] ^= 0x40;
+    let executor = SplitPrepareTransactionExecutor::new(
+        BlockchainConfig::default(), split_info(THIS_ADDR, wrong_addr)
+    );
+    assert!(executor.execute_with_params(None, &mut account_root, params()).is_err());
+
+    // account is not split deeper than shard prefix
+    let mut info = split_info(THIS_ADDR, sibling_addr());
+    info.cur_shard_pfx_len = 1;
+    let executor = MergePrepareTransactionExecutor::new(BlockchainConfig::default(), info);
+    assert!(executor.execute_with_params(None, &mut account_root, params()).is_err());
+}
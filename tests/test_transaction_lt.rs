@@ -0,0 +1,58 @@
+/*
+* Copyright 2018-2020 TON DEV SOLUTIONS LTD.
+*
+* Licensed under the SOFTWARE EVALUATION License (the "License"); you may not use
+* this file except in compliance with the License.  You may obtain a copy of the
+* License at: https://ton.dev/licenses
+*
+* Unless required by applicable law or agreed to in writing, software
+* distributed under the License is distributed on an "AS IS" BASIS,
+* WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+* See the License for the specific TON DEV software governing permissions and
+* limitations under the License.
+*/
+
+use std::{sync::{atomic::{AtomicU64, Ordering}, Arc}, thread};
+use ton_executor::TransactionLt;
+
+#[test]
+fn test_reserve_respects_account_lt() {
+    let block_next_lt = AtomicU64::new(100);
+    let lt = TransactionLt::reserve(&block_next_lt, 200, None).commit(203);
+    assert_eq!(lt, TransactionLt { start: 200, end: 203 });
+    assert_eq!(block_next_lt.load(Ordering::SeqCst), 203);
+}
+
+#[test]
+fn test_reservation_is_returned_if_not_committed() {
+    let block_next_lt = AtomicU64::new(100);
+    {
+        let reservation = TransactionLt::reserve(&block_next_lt, 0, None);
+        assert_eq!(reservation.start(), 100);
+    }
+    assert_eq!(block_next_lt.load(Ordering::SeqCst), 100);
+}
+
+#[test]
+fn test_parallel_reservations_do_not_overlap() {
+    let block_next_lt = Arc::new(AtomicU64::new(1000));
+    let threads: Vec<_> = (0..8).map(|i| {
+        let block_next_lt = block_next_lt.clone();
+        thread::spawn(move || {
+            (0..100).map(|_| {
+                let reservation = TransactionLt::reserve(&block_next_lt, 0, None);
+                let start = reservation.start();
+                // transaction with `i` outbound messages
+                reservation.commit(start + 1 + i)
+            }).collect::<Vec<_>>()
+        })
+    }).collect();
+    let mut intervals: Vec<TransactionLt> = threads.into_iter()
+        .flat_map(|handle| handle.join().unwrap())
+        .collect();
+    intervals.sort_by_key(|lt| lt.start);
+    for pair in intervals.windows(2) {
+        assert!(pair[0].end <= pair[1].start, "{:?} overlaps {:?}", pair[0], pair[1]);
+    }
+    assert!(block_next_lt.load(Ordering::SeqCst) >= intervals.last().unwrap().end);
+}